@@ -0,0 +1,148 @@
+// Tiny GTP engine for tests of engines that run in a child
+// process. It answers standard commands with canned responses.
+//
+// Options:
+//   --log <line>       Write the line to stderr at startup.
+//   --hang <command>   Never answer the command.
+//   --delay <command>:<milliseconds>
+//                      Wait before answering the command.
+//   --crash <command>  Exit with status 1 when receiving the command.
+//   --helper <seconds> Start a helper process that sleeps for seconds
+//                      and write "Helper <pid>" to stderr.
+//   --sleep <seconds>  Sleep before reading commands.
+//   --analyze <command> Know the analysis command, e.g. kata-analyze,
+//                      and stream a report for it until the next
+//                      command.
+
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut hang = Vec::new();
+    let mut crash = Vec::new();
+    let mut delay = Vec::new();
+    let mut analyze = Vec::new();
+    for pair in args.chunks(2) {
+	match (pair[0].as_str(), pair.get(1)) {
+	    ("--log", Some(line)) => { eprintln!("{line}"); },
+	    ("--hang", Some(command)) => { hang.push(command.clone()); },
+	    ("--crash", Some(command)) => { crash.push(command.clone()); },
+	    ("--delay", Some(spec)) => {
+		match spec.split_once(':').and_then(|(command, ms)| Some((command.to_string(), ms.parse::<u64>().ok()?))) {
+		    Some(x) => { delay.push(x); },
+		    None => {
+			eprintln!("Illegal delay: {spec}");
+			process::exit(2);
+		    },
+		}
+	    },
+	    ("--helper", Some(seconds)) => {
+		let helper = process::Command::new(env::current_exe().unwrap())
+		    .args(["--sleep", seconds])
+		    .stdin(process::Stdio::null())
+		    .spawn().expect("Failed to start helper!");
+		eprintln!("Helper {}", helper.id());
+	    },
+	    ("--analyze", Some(command)) => { analyze.push(command.clone()); },
+	    ("--sleep", Some(seconds)) => { thread::sleep(Duration::from_secs(seconds.parse().unwrap())); },
+	    _ => {
+		eprintln!("Illegal arguments: {args:?}");
+		process::exit(2);
+	    },
+	}
+    }
+
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut streaming = false;
+    for line in stdin.lock().lines() {
+	let line = line.expect("Failed to read stdin!");
+	let mut tokens: Vec<&str> = line.split_whitespace().collect();
+	if tokens.is_empty() {
+	    continue;
+	}
+	let id = if tokens[0].parse::<u32>().is_ok() { tokens.remove(0) } else { "" };
+	let command_name = tokens.first().copied().unwrap_or_default();
+	let args = &tokens[1.min(tokens.len())..];
+
+	if streaming {
+	    // Any command ends the analysis.
+	    stdout.write_all(b"\n").expect("Failed to write stdout!");
+	    streaming = false;
+	}
+
+	if crash.iter().any(|x| x == command_name) {
+	    eprintln!("Crashing on {command_name}.");
+	    process::exit(1);
+	}
+	if hang.iter().any(|x| x == command_name) {
+	    loop {
+		thread::sleep(Duration::from_secs(1));
+	    }
+	}
+
+	if let Some((_, ms)) = delay.iter().find(|(x, _)| x == command_name) {
+	    thread::sleep(Duration::from_millis(*ms));
+	}
+
+	if analyze.iter().any(|x| x == command_name) {
+	    let report = format!("={id}\ninfo move B2 visits 10 winrate 0.75 scoreLead 1.5 order 0 pv B2\n");
+	    stdout.write_all(report.as_bytes()).expect("Failed to write stdout!");
+	    stdout.flush().expect("Failed to flush stdout!");
+	    streaming = true;
+	    continue;
+	}
+
+	let response = match respond(command_name, args, &analyze) {
+	    Ok(s) => format!("={id} {s}\n\n"),
+	    Err(s) => format!("?{id} {s}\n\n"),
+	};
+	stdout.write_all(response.as_bytes()).expect("Failed to write stdout!");
+	stdout.flush().expect("Failed to flush stdout!");
+
+	if command_name == "quit" {
+	    break;
+	}
+    }
+}
+
+
+fn respond(command_name: &str, args: &[&str], analyze: &[String]) -> Result<String, &'static str> {
+    const COMMANDS: [&str; 20] = [
+	"protocol_version", "name", "version", "known_command", "list_commands", "quit",
+	"boardsize", "clear_board", "komi", "play", "genmove", "fixed_handicap",
+	"place_free_handicap", "set_free_handicap", "undo", "time_settings", "time_left",
+	"final_score", "final_status_list", "showboard",
+    ];
+    let mut commands = COMMANDS.to_vec();
+    commands.extend(analyze.iter().map(|x| x.as_str()));
+
+    let response = match command_name {
+	"protocol_version" => "2",
+	"name" => "Fake",
+	"version" => "0.1",
+	"known_command" => {
+	    if args.first().is_some_and(|x| commands.contains(x)) { "true" } else { "false" }
+	},
+	"list_commands" => { return Ok(commands.join("\n")); },
+	"genmove" => "D4",
+	"fixed_handicap" | "place_free_handicap" => "D4 Q16",
+	"final_score" => "B+0.5",
+	"final_status_list" => "",
+	"showboard" => "\n   A B\n 2 . . 2\n 1 X . 1\n   A B",
+	"boardsize" => {
+	    match args.first().and_then(|x| x.parse::<u32>().ok()) {
+		Some(2..=25) => "",
+		_ => { return Err("unacceptable size"); },
+	    }
+	},
+	_ if COMMANDS.contains(&command_name) => "",
+	_ => { return Err("unknown command"); },
+    };
+    Ok(response.to_string())
+}
@@ -1,265 +1,264 @@
 use crate::gtp::*;
-use crate::smart_child::SmartChild;
-use std::process::ChildStdout;
-use std::io::{Read, Write};
-use std::sync::mpsc::{Sender, Receiver, channel};
+use crate::gtp_katago::KataGoGtpExtensions;
+use crate::game_engine::GameEngine;
+use crate::smart_child::{LaunchSpec, SmartChild};
+use crate::engine_log::{EngineLog, ENGINE_LOG_CAPACITY};
+use crate::gtp_transcript::Transcript;
+use crate::gtp_channel::{GtpChannel, PendingResponse};
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
 use std::thread;
-use std::str;
+use std::time::Duration;
+
+
+// Default deadline for a single command. Long searches can raise it
+// with set_command_timeout.
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(120);
+
+// Time the engine gets to answer quit and exit before it is
+// terminated.
+const QUIT_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Number of stderr lines attached to errors of an exited engine.
+const EXIT_ERROR_LOG_LINES: usize = 10;
 
 
 pub struct ChildProcessEngine {
-    id: u32,
+    spec: LaunchSpec, // Kept for restarting the engine.
     child: SmartChild,
-    writer_thread: Option<thread::JoinHandle<()>>, // writer_thread and tx_channel are declared as Option because they are taken during quit.
-    child_stdout: ChildStdout,
-    tx_channel: Option<Sender<String>>,
+    channel: Option<GtpChannel>, // Taken during shutdown.
+    log: EngineLog, // stderr of the engine, kept across restarts.
+    log_thread: thread::JoinHandle<()>,
+    transcript: Option<Transcript>,
+    capabilities: Option<Capabilities>, // Queried on first use.
 }
 
 
 impl ChildProcessEngine {
-    pub fn new(command: &str) -> Result<Self, String> {
-	let mut child = SmartChild::from_command_str(command)?;
-
-	let mut stdin = child.take_stdin()?;
-	let stdout = child.take_stdout()?;
-
-	// Writing to child with a thread in case OS I/O pipes cause a deadlock.
-	let (tx, rx): (Sender<String>, Receiver<String>) = channel();
-
-	let writer = thread::spawn(move || {
-	    loop {
-		let s = rx.recv();
-		match s {
-		    Ok(s) => { stdin.write_all(s.as_bytes()).expect("Failed to write to stdin!"); },
-		    Err(_) => { break; }
-		}
-	    }
-	});
+    pub fn new(command: &str) -> Result<Self, GtpError> {
+	Self::from_spec(LaunchSpec::from_command_str(command).map_err(GtpError::Io)?)
+    }
+
+    // The init script of the spec is sent as GTP commands after the
+    // engine starts, e.g. to set the engine's level. A failing
+    // command fails the start.
+    pub fn from_spec(spec: LaunchSpec) -> Result<Self, GtpError> {
+	Self::start(spec, EngineLog::new(ENGINE_LOG_CAPACITY))
+    }
+
+    fn start(spec: LaunchSpec, log: EngineLog) -> Result<Self, GtpError> {
+	let mut child = SmartChild::spawn(&spec).map_err(GtpError::Io)?;
+
+	let stdin = child.take_stdin().map_err(GtpError::Io)?;
+	let stdout = child.take_stdout().map_err(GtpError::Io)?;
+	let stderr = child.take_stderr().map_err(GtpError::Io)?;
+
+	// The channel reads the responses on a thread, so that a hanging
+	// engine can be timed out, and lets analysis stream while the
+	// engine takes other commands.
+	let channel = GtpChannel::new(stdout, stdin);
+
+	let log_thread = log.drain(stderr);
 	
-	Ok(Self {
-	    id: 0,
+	let mut engine = Self {
+	    spec: spec,
 	    child: child,
-	    writer_thread: Some(writer),
-	    child_stdout: stdout,
-	    tx_channel: Some(tx),
-	})
+	    channel: Some(channel),
+	    log: log,
+	    log_thread: log_thread,
+	    transcript: None,
+	    capabilities: None,
+	};
+	for c in engine.spec.init_script.clone() {
+	    engine.send_command(&c)?;
+	}
+	Ok(engine)
     }
-}
 
+    // Sends quit and waits for the engine to exit, terminating it if it
+    // doesn't within QUIT_TIMEOUT. Does nothing when the engine is
+    // already shut down. Called when the engine is dropped.
+    pub fn shutdown(&mut self) -> Result<(), GtpError> {
+	if self.channel.is_none() {
+	    return Ok(());
+	}
 
-impl GTPEngineRaw for ChildProcessEngine {
-    fn gen_command_id(&mut self) -> u32 {
-	self.id += 1;
-	self.id
-    }
-    
-    fn write_to_engine(&self, s: &str) -> Result<(), String> {
-	// s does not have go end with double new line, they are added here.
-	let mut s = String::from(s.trim());
-	s.push_str("\n\n");
-	match &self.tx_channel {
-	    Some(tx) => tx.send(s).map_err(|_| "Failed to send to channel!".to_string()),
-	    None => Err("Sender is None!".to_string()),
+	self.set_command_timeout(Some(QUIT_TIMEOUT));
+	let quit = self.send_command("quit");
+	if let Err(e) = &quit {
+	    println!("Engine didn't answer quit: {e}");
 	}
-    }
+	let grace_period = if quit.is_ok() { QUIT_TIMEOUT } else { Duration::ZERO };
 
-    fn read_from_engine(&mut self) -> Result<String, String> {
-	let mut s = Vec::new();
-	loop {
-	    let mut buff = [0; 1];
-	    let n = self.child_stdout.read(&mut buff).map_err(|_| "Engine sent EOF. This is unexpected!".to_string())?;
-	    if n == 0 {
-		return Err("Engine sent EOF. This is unexpected!".to_string());
-	    } else if n == 1 {
-		// do nothing
-	    } else {
-		return Err(format!("read method should return 1, received {n}"));
-	    }
-	    s.push(buff[0]);
+	// The reader thread of the channel ends once the exited child
+	// closes its stdout.
+	self.channel.take();
+	self.child.terminate(grace_period).map_err(GtpError::Io)?;
+	Ok(())
+    }
 
-	    if s.last_chunk::<2>().is_some_and(|x| x == "\n\n".as_bytes())
-		|| s.last_chunk::<4>().is_some_and(|x| x == "\r\n\r\n".as_bytes()) {
+    // Error for an engine that is gone, with the tail of its stderr,
+    // which usually tells why, e.g. a bad model path.
+    fn exited_error(&mut self, s: &str) -> GtpError {
+	// Closing stdout and exiting are not simultaneous. Give the
+	// process a moment to exit and the log thread to read the last
+	// lines.
+	let mut status = None;
+	for _ in 0..10 {
+	    status = self.exit_status();
+	    if status.is_some() && self.log_thread.is_finished() {
 		break;
 	    }
+	    thread::sleep(Duration::from_millis(50));
 	}
 
-	let s = str::from_utf8(&s).map_err(|_| format!("String cannot be screated from utf8."))?
-	    .trim();
-	Ok(s.to_string())
+	let mut s = match status {
+	    Some(status) => format!("{s} Engine exited with {status}."),
+	    None => s.to_string(),
+	};
+	let tail = self.log.tail(EXIT_ERROR_LOG_LINES);
+	if !tail.is_empty() {
+	    s.push_str("\nEngine log:\n");
+	    s.push_str(&tail);
+	}
+	GtpError::ProcessExited(s)
     }
 }
 
 
-impl GTPEngineMinimal for ChildProcessEngine {
-    fn protocol_version(&mut self) -> Result<u32, String> {
-	let s = self.send_command("protocol_version")?;
-	s.parse::<u32>().map_err(|_| format!("{} cannot be parsed into u32!", s))
+impl GTPEngineRaw for ChildProcessEngine {
+    fn gen_command_id(&mut self) -> u32 {
+	self.channel.as_mut().map(|x| x.gen_command_id()).unwrap_or_default()
     }
-
-    fn name(&mut self) -> Result<String, String> {
-	self.send_command("name")
+    
+    fn write_to_engine(&self, s: &str) -> Result<(), GtpError> {
+	match &self.channel {
+	    Some(channel) => channel.write_to_engine(s),
+	    None => Err(shut_down_error()),
+	}
     }
 
-    fn version(&mut self) -> Result<String, String> {
-	self.send_command("version")
+    fn read_from_engine(&mut self) -> Result<String, GtpError> {
+	let r = match &mut self.channel {
+	    Some(channel) => channel.read_from_engine(),
+	    None => Err(shut_down_error()),
+	};
+	match r {
+	    Err(GtpError::ProcessExited(s)) => Err(self.exited_error(&s)),
+	    r => r,
+	}
     }
 
-    fn known_command(&mut self, command_name: &str) -> Result<bool, String> {
-	if !is_single_token(command_name) {
-	    return Err(format!("command_name '{command_name}' is not single token!"));
-	}
-	let s = self.send_command(&format!("known_command {}", command_name))?;
-	if s == "true" {
-	    Ok(true)
-	} else if s == "false" {
-	    Ok(false)
-	} else {
-	    Err(format!("command_name '{command_name}' is not 'true' or 'false'!"))
+    fn send_streaming_command(&mut self, command: &str) -> Result<PendingResponse, GtpError> {
+	match &self.channel {
+	    Some(channel) => channel.send(command),
+	    None => Err(shut_down_error()),
 	}
     }
 
-    fn list_commands(&mut self) -> Result<Vec<String>, String> {
-	let s = self.send_command("list_commands")?;
-	Ok(s.split_whitespace().map(|x| String::from(x)).collect())
+    fn get_transcript(&mut self) -> Option<&mut Transcript> {
+	self.transcript.as_mut()
     }
+}
 
-    // Note: quit method takes ownership of self, sends quit command,
-    // terminates the writer thread, waits for the child to
-    // quit. Destructor of Self doesn't call quit, the user is
-    // expected to call quit.
-    
-    fn quit(mut self) -> Result<(), String> {
-	self.send_command("quit")?;
 
-	self.tx_channel.take(); // Replace tx with None so it is dropped.
-	self.writer_thread
-	    .take().ok_or("Writer thread couldn't be taken!".to_string())?
-	    .join().map_err(|_| "Writer thread failed to join!".to_string())?;
-	
-	self.child.wait().map_err(|_| "Child process failed to exit!".to_string())?;
-	Ok(())
-    }
+fn shut_down_error() -> GtpError {
+    GtpError::ProcessExited("Engine is shut down.".to_string())
+}
 
-    fn boardsize(&mut self, size: u32) -> Result<(), String> {
-	self.send_command(&format!("boardsize {size}"))?;
-	Ok(())
-    }
 
-    fn clear_board(&mut self) -> Result<(), String> {
-	self.send_command("clear_board")?;
-	Ok(())
+impl GTPEngineMinimal for ChildProcessEngine {
+    // Same as dropping the engine, but returns the errors of
+    // shutdown.
+    fn quit(mut self) -> Result<(), GtpError> {
+	self.shutdown()
     }
+}
 
-    fn komi(&mut self, new_komi: f32) -> Result<(), String> {
-	self.send_command(&format!("komi {}", new_komi.to_string()))?;
-	Ok(())
-    }
 
-    fn play(&mut self, gtp_move: GTPMove) -> Result<(), String> {
-	self.send_command(&format!("play {}", gtp_move.to_string()?))?;
-	Ok(())
-    }
+impl GTPEngineTournament for ChildProcessEngine {}
 
-    fn genmove(&mut self, color: Color) -> Result<GenMove, String> {
-	let s = self.send_command(&format!("genmove {}", color.to_string()))?;
-	if s == "resign" {
-	    Ok(GenMove::Resign)
-	} else {
-	    Ok(GenMove::Play(Vertex::from_string(&s)?))
-	}
-    }
-}
 
+impl GTPEngineRegression for ChildProcessEngine {}
 
-impl GTPEngineTournament for ChildProcessEngine {
-    fn fixed_handicap(&mut self, number_of_stones: u32) -> Result<Vec<Vertex>, String> {
-	let s = self.send_command(&format!("fixed_handicap {number_of_stones}"))?;
-	let mut v = Vec::new();
-	for tok in s.split_whitespace() {
-	    v.push(Vertex::from_string(tok)?);
-	}
-	Ok(v)
-    }
 
-    fn place_free_handicap(&mut self, number_of_stones: u32) -> Result<Vec<Vertex>, String> {
-	let s = self.send_command(&format!("place_free_handicap {number_of_stones}"))?;
-	let mut v = Vec::new();
-	for tok in s.split_whitespace() {
-	    v.push(Vertex::from_string(tok)?);
-	}
-	Ok(v)
-    }
+impl GTPEngineExtendedCorePlay for ChildProcessEngine {}
 
-    fn set_free_handicap(&mut self, vertices: Vec<Vertex>) -> Result<(), String> {
-	let mut command = String::from("set_free_handicap");
-	for v in vertices {
-	    command.push_str(" ");
-	    command.push_str(&v.to_string()?);
-	}
-	self.send_command(&command)?;
-	Ok(())
-    }
-}
 
+impl GTPEngineExtendedTournament for ChildProcessEngine {}
 
-impl GTPEngineRegression for ChildProcessEngine {
-    fn loadsgf(&mut self, filename: &str, move_number: u32) -> Result<(), String> {
-	self.send_command(&format!("loadsgf {filename} {move_number}"))?;
-	Ok(())
+
+impl GTPEngineDebug for ChildProcessEngine {}
+
+
+impl GTPEngineLeelaZero for ChildProcessEngine {}
+
+
+impl KataGoGtpExtensions for ChildProcessEngine {}
+
+
+impl GameEngine for ChildProcessEngine {
+    fn get_capabilities(&self) -> Option<&Capabilities> {
+	self.capabilities.as_ref()
     }
 
-    fn reg_genmove(&mut self, color: Color) -> Result<GenMove, String> {
-	let s = self.send_command(&format!("reg_genmove {}", color.to_string()))?;
-	if s == "resign" {
-	    Ok(GenMove::Resign)
-	} else {
-	    Ok(GenMove::Play(Vertex::from_string(&s)?))
+    fn query_capabilities(&mut self) -> Result<&Capabilities, GtpError> {
+	if self.capabilities.is_none() {
+	    self.capabilities = Some(Capabilities::query(self)?);
 	}
+	Ok(self.capabilities.as_ref().unwrap())
     }
-}
-
 
-impl GTPEngineExtendedCorePlay for ChildProcessEngine {
-    fn undo(&mut self) -> Result<(), String> {
-	self.send_command("undo")?;
-	Ok(())
+    fn get_log(&self) -> Option<&EngineLog> {
+	Some(&self.log)
     }
-}
 
+    // Records the session to a new transcript file in dir, named
+    // after the engine program.
+    fn record_transcript(&mut self, dir: &Path) -> Result<PathBuf, String> {
+	let transcript = Transcript::create(dir, &self.spec.get_program_name(), &self.spec.to_command_str())?;
+	let path = transcript.get_path().to_path_buf();
+	println!("Recording GTP transcript to {path:?}");
+	self.transcript = Some(transcript);
+	Ok(path)
+    }
 
-impl GTPEngineExtendedTournament for ChildProcessEngine {
-    fn time_settings(&mut self, main_time: u32, byo_yomi_time: u32, byo_yomi_stones: u32) -> Result<(), String> {
-	self.send_command(&format!("time_settings {main_time} {byo_yomi_time} {byo_yomi_stones}"))?;
-	Ok(())
+    fn set_command_timeout(&mut self, timeout: Option<Duration>) {
+	if let Some(channel) = &mut self.channel {
+	    channel.set_command_timeout(timeout);
+	}
     }
 
-    fn time_left(&mut self, color: Color, time: u32, stones: u32) -> Result<(), String> {
-	self.send_command(&format!("time_left {} {} {}", color.to_string(), time, stones))?;
-	Ok(())
+    fn get_channel(&self) -> Option<GtpChannel> {
+	self.channel.clone()
     }
 
-    fn final_score(&mut self) -> Result<Score, String> {
-	let s = self.send_command("final_score")?;
-	if !is_single_token(&s) {
-	    return Err(format!("Final score '{s}' is not a single token!"));
-	}
-	Score::from_string(&s)
+    fn exit_status(&mut self) -> Option<ExitStatus> {
+	self.child.try_wait().ok().flatten()
     }
 
-    fn final_status_list(&mut self, status: Status) -> Result<Vec<Vertex>, String> {
-	let s = self.send_command(&format!("final_status_list {}", status.to_string()))?;
-	let mut v = Vec::new();
-	for tok in s.split_whitespace() {
-	    v.push(Vertex::from_string(tok)?);
+    // Replaces the engine process with a new one started with the
+    // same command.
+    fn restart(&mut self) -> Result<(), GtpError> {
+	let command = self.spec.to_command_str();
+	println!("Restarting engine: {command}");
+	self.log.push(format!("--- Restarting engine: {command} ---"));
+	let mut engine = Self::start(self.spec.clone(), self.log.clone())?;
+	if let Some(channel) = &self.channel {
+	    engine.set_command_timeout(channel.get_command_timeout());
 	}
-	Ok(v)
+	engine.transcript = self.transcript.take();
+	*self = engine; // Old child is killed if it is still running.
+	Ok(())
     }
 }
 
 
-impl GTPEngineDebug for ChildProcessEngine {    
-    fn showboard(&mut self) -> Result<String, String> {
-	self.send_command("showboard")
+impl Drop for ChildProcessEngine {
+    fn drop(&mut self) {
+	if let Err(e) = self.shutdown() {
+	    println!("Engine shutdown failed: {e}");
+	}
     }
 }
 
@@ -267,10 +266,13 @@ impl GTPEngineDebug for ChildProcessEngine {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::gtp_transcript::{RecordedSession, ReplayEngine};
+    use crate::mock_engine::fake_engine_command;
+    use std::env;
 
     #[test]
     fn gtp() {
-	let mut engine = ChildProcessEngine::new("katago.exe gtp").unwrap();
+	let mut engine = ChildProcessEngine::new(&fake_engine_command("")).unwrap();
 	
 	println!("Protocol version: {:?}", engine.protocol_version().unwrap());
 	println!("Name: {:?}", engine.name().unwrap());
@@ -278,9 +280,16 @@ mod tests {
 	println!("Is known_command a known command: {:?}", engine.known_command("known_command").unwrap());
 	println!("Is asdf a known command: {:?}", engine.known_command("asdf").unwrap());
 	println!("List commands: {:?}", engine.list_commands().unwrap());
+	assert_eq!(engine.name().unwrap(), "Fake");
+	assert!(!engine.known_command("asdf").unwrap());
+	assert_eq!(engine.list_commands().unwrap().len(), 20);
+	assert!(engine.get_capabilities().is_none());
+	assert!(engine.query_capabilities().unwrap().has(Capability::Undo));
+	assert!(!engine.get_capabilities().unwrap().has(Capability::KataAnalyze));
 
 	engine.boardsize(19).unwrap();
 	println!("Set board size to 19x19.");
+	assert!(matches!(engine.boardsize(99), Err(GtpError::EngineFailure(_))));
 	engine.clear_board().unwrap();
 	println!("Board cleared.");
 	engine.komi(2.5).unwrap();
@@ -311,11 +320,99 @@ mod tests {
 	println!("Final status list for seki : {:?}", engine.final_status_list(Status::Seki).unwrap());
 	println!("Final status list for dead : {:?}", engine.final_status_list(Status::Dead).unwrap());
 
-	// loadsgf is note tested
-	//println!("Engine generate move for black for regression: {:?}", engine.reg_genmove(Color::Black).unwrap());
+	// loadsgf and reg_genmove are covered with the mock engine in gtp.rs.
 	println!("Showing board:\n{}", engine.showboard().unwrap());
 
 	engine.quit().unwrap();
 	println!("Quited");
     }
+
+    #[test]
+    fn transcript() {
+	let dir = env::temp_dir().join(format!("go-game-engine-transcript-{}", std::process::id()));
+	let mut engine = ChildProcessEngine::new(&fake_engine_command("")).unwrap();
+	let path = engine.record_transcript(&dir).unwrap();
+	engine.boardsize(19).unwrap();
+	let genmove = engine.genmove(Color::Black).unwrap();
+	let showboard = engine.showboard().unwrap();
+	engine.quit().unwrap();
+
+	let mut replay = ReplayEngine::new(RecordedSession::load(&path).unwrap());
+	std::fs::remove_dir_all(&dir).unwrap();
+	replay.boardsize(19).unwrap();
+	assert_eq!(replay.genmove(Color::Black).unwrap(), genmove);
+	assert_eq!(replay.showboard().unwrap(), showboard);
+	replay.quit().unwrap();
+    }
+
+    #[test]
+    fn timeout() {
+	let mut engine = ChildProcessEngine::new(&fake_engine_command("--hang genmove")).unwrap();
+	engine.set_command_timeout(Some(Duration::from_millis(200)));
+
+	let e = engine.genmove(Color::Black).unwrap_err();
+	assert!(matches!(e, GtpError::Timeout(_)));
+	assert!(e.is_recoverable());
+
+	// The engine stays usable. A response that arrives after its
+	// command timed out is discarded rather than taken as the
+	// response of the next command.
+	let mut engine = ChildProcessEngine::new(&fake_engine_command("--delay genmove:500")).unwrap();
+	engine.set_command_timeout(Some(Duration::from_millis(200)));
+	assert!(matches!(engine.genmove(Color::Black), Err(GtpError::Timeout(_))));
+	engine.set_command_timeout(Some(Duration::from_secs(5)));
+	assert_eq!(engine.name().unwrap(), "Fake");
+	assert_eq!(engine.version().unwrap(), "0.1");
+
+	// Starting doesn't wait for an engine that is still loading.
+	let start = std::time::Instant::now();
+	let mut engine = ChildProcessEngine::new(&fake_engine_command("--sleep 1")).unwrap();
+	assert!(start.elapsed() < Duration::from_secs(1));
+	assert!(engine.query_capabilities().unwrap().has(Capability::Undo));
+    }
+
+    #[test]
+    fn crash() {
+	let mut engine = ChildProcessEngine::new(&fake_engine_command("--log bad_model_path --crash genmove")).unwrap();
+	assert_eq!(engine.name().unwrap(), "Fake");
+
+	match engine.genmove(Color::Black) {
+	    Err(GtpError::ProcessExited(s)) => { assert!(s.contains("bad_model_path"), "{s}"); },
+	    r => { panic!("Expected ProcessExited, got {r:?}"); },
+	}
+	assert!(engine.exit_status().is_some());
+	assert!(engine.get_log().unwrap().get_lines().contains(&"Crashing on genmove.".to_string()));
+
+	engine.restart().unwrap();
+	assert_eq!(engine.name().unwrap(), "Fake");
+	engine.quit().unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn teardown() {
+	// Zombies count as gone, the test process may not reap orphans.
+	let is_alive = |pid: &str| match std::fs::read_to_string(format!("/proc/{pid}/stat")) {
+	    Ok(stat) => stat.split_whitespace().nth(2) != Some("Z"),
+	    Err(_) => false,
+	};
+
+	let engine = ChildProcessEngine::new(&fake_engine_command("--helper 60 --hang quit")).unwrap();
+	let mut helper = None;
+	for _ in 0..50 {
+	    helper = engine.get_log().unwrap().get_lines().iter().find_map(|x| x.strip_prefix("Helper ").map(|x| x.to_string()));
+	    if helper.is_some() {
+		break;
+	    }
+	    thread::sleep(Duration::from_millis(20));
+	}
+	let helper = helper.unwrap();
+	assert!(is_alive(&helper));
+
+	let start = std::time::Instant::now();
+	drop(engine);
+	assert!(start.elapsed() < QUIT_TIMEOUT * 3);
+	thread::sleep(Duration::from_millis(100));
+	assert!(!is_alive(&helper));
+    }
 }
@@ -1,9 +1,18 @@
-use crate::model::{Model, Point, Stone, Turn};
+use crate::model::{Model, Point, Stone, Turn, EngineSlot};
 use crate::katago_installer::*;
+use crate::network_manager::NetworkInfo;
+use crate::katago_config::{ConfigKind, COMMON_SETTINGS};
+use crate::install_manifest::InstallManifest;
+use crate::gtp::Capability;
+use crate::engine_config::{CustomEngine, EngineConfig, ENGINE_CONFIG_FILE};
+use crate::game_engine::GameEngine;
+use crate::socket_engine::SocketEngine;
 use eframe::egui;
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc::Receiver;
+use std::time::Duration;
 use crate::smart_thread::{self, SmartHandle};
 
 
@@ -17,7 +26,21 @@ pub struct EguiView {
     // execution. The view provides a second layer of protection for
     // its own purposes.
     katago_installer_status: Arc<Mutex<KataGoInstallerStatus>>,
+    katago_backend_choice: Option<Backend>, // None installs the first detected backend that works.
+    offline_install_sources: String, // Paths of local KataGo files or directories, one per line.
+    detected_backends: Vec<Backend>,
     smart_handles: Vec<SmartHandle>,
+    katago_installer_handle: Option<SmartHandle>, // Of the last installer operation, for cancelling it.
+    network_source: String, // URL or path of a network to add.
+    external_katago: Vec<PathBuf>, // Found on PATH and in common locations.
+    external_exe: String, // Path of an external KataGo to adopt.
+    external_models: String, // Paths of its networks, one per line.
+    katago_config_form: KataGoConfigForm,
+    transcript_dir: PathBuf,
+    engine_config: EngineConfig,
+    engine_config_path: PathBuf,
+    custom_engine_form: CustomEngineForm,
+    custom_engine_status: Arc<Mutex<HashMap<String, String>>>, // Test results keyed by engine name.
 }
 
 struct Workspace {
@@ -32,6 +55,8 @@ struct Workspace {
     white_area_score: i32,
     new_black_captures: i32,
     new_white_captures: i32,
+    restart_crashed_engines: bool,
+    show_engine_log: bool,
 }
 
 struct KataGoInstallerStatus {
@@ -51,7 +76,30 @@ struct NewWorkspaceSetup {
     board_size: usize,
     count: usize,
     game_mode: GameMode,
+    computer_engine: EngineType,
+    computer_network: Option<String>, // KataGo network file, None for the default one.
     analysis_engine: Option<EngineType>,
+    analysis_network: Option<String>,
+    computer_address: String, // Of a remote computer engine, "host:port" or "unix:<path>".
+    analysis_address: String,
+    record_transcripts: bool,
+}
+
+// Text fields for registering a custom engine.
+#[derive(Default)]
+struct CustomEngineForm {
+    name: String,
+    command: String,
+    working_dir: String,
+    env: String, // KEY=VALUE lines.
+    init_script: String, // Path of a file of GTP commands.
+    init_commands: String, // One command per line.
+}
+
+// Common settings of a KataGo config, None until loaded from the file.
+struct KataGoConfigForm {
+    kind: ConfigKind,
+    values: Option<BTreeMap<String, String>>,
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -67,9 +115,11 @@ enum GameMode {
     HumanVsComputer(Turn), // turn is the human's color
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 enum EngineType {
     KataGo,
+    Custom(String), // Name in the engine config.
+    Remote, // Engine serving GTP on a socket, at the address of the setup.
 }
 
 
@@ -90,6 +140,8 @@ impl Clone for Workspace {
 	    white_area_score: self.white_area_score,
 	    new_black_captures: self.new_black_captures,
 	    new_white_captures: self.new_white_captures,
+	    restart_crashed_engines: self.restart_crashed_engines,
+	    show_engine_log: self.show_engine_log,
 	}
     }
 }
@@ -101,30 +153,90 @@ impl NewWorkspaceSetup {
 	    board_size: 13,
 	    count: 0,
 	    game_mode: GameMode::HumanVsHuman,
+	    computer_engine: EngineType::KataGo,
+	    computer_network: None,
 	    analysis_engine: None,
+	    analysis_network: None,
+	    computer_address: String::new(),
+	    analysis_address: String::new(),
+	    record_transcripts: false,
+	}
+    }
+}
+
+
+impl CustomEngineForm {
+    fn to_custom_engine(&self) -> CustomEngine {
+	let working_dir = self.working_dir.trim();
+	let init_script = self.init_script.trim();
+	CustomEngine {
+	    name: self.name.trim().to_string(),
+	    command: self.command.trim().to_string(),
+	    working_dir: if working_dir.is_empty() { None } else { Some(PathBuf::from(working_dir)) },
+	    env: self.env.lines()
+		.filter_map(|x| x.split_once('='))
+		.map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+		.collect::<BTreeMap<_, _>>(),
+	    init_script: if init_script.is_empty() { None } else { Some(PathBuf::from(init_script)) },
+	    init_commands: self.init_commands.lines()
+		.map(|x| x.trim().to_string())
+		.filter(|x| !x.is_empty())
+		.collect(),
 	}
     }
 }
 
 
 impl EguiView {
-    pub fn make(katago_install_dir: &Path) -> Result<Self, String> {
-	let katago_installer = KataGoInstaller::new(katago_install_dir);
+    pub fn make(app_dir: &Path) -> Result<Self, String> {
+	let katago_installer = KataGoInstaller::new(&app_dir.join("katago"));
 	let katago_installer_status = KataGoInstallerStatus {
 	    is_installed: Some(katago_installer.is_installed()),
 	    is_operational: None, // Checking this probably takes several seconds, not doing here.
 	    is_tuned: Some(katago_installer.is_tuned()),
 	};
 
-	let view = Self {
+	let external_katago = katago_installer.find_external();
+
+	let engine_config_path = app_dir.join(ENGINE_CONFIG_FILE);
+	let engine_config = EngineConfig::load(&engine_config_path).unwrap_or_else(|s| {
+	    println!("{s}");
+	    EngineConfig::default()
+	});
+
+	let mut view = Self {
 	    workspaces: Vec::new(),
 	    wspc: None,
 	    mode: ViewMode::CreateWorkspace,
 	    new_workspace_setup: NewWorkspaceSetup::default(),
 	    katago_installer: katago_installer,
 	    katago_installer_status: Arc::new(Mutex::new(katago_installer_status)),
+	    katago_backend_choice: None,
+	    offline_install_sources: String::new(),
+	    detected_backends: Backend::detect(),
 	    smart_handles: Vec::new(),
+	    katago_installer_handle: None,
+	    network_source: String::new(),
+	    external_katago: external_katago,
+	    external_exe: String::new(),
+	    external_models: String::new(),
+	    katago_config_form: KataGoConfigForm { kind: ConfigKind::Analysis, values: None },
+	    transcript_dir: app_dir.join("transcripts"),
+	    engine_config: engine_config,
+	    engine_config_path: engine_config_path,
+	    custom_engine_form: CustomEngineForm::default(),
+	    custom_engine_status: Arc::new(Mutex::new(HashMap::new())),
 	};
+
+	// Hashing the models takes a while, verifying in the background.
+	view.do_katago_installer_op(|installer, status, kill_signal_rx| {
+	    let deleted = installer.verify(&kill_signal_rx)?;
+	    if !deleted.is_empty() {
+		println!("Deleted corrupt KataGo files, reinstall to download them again: {deleted:?}");
+	    }
+	    status.is_installed = Some(installer.is_installed());
+	    Ok(())
+	});
 	Ok(view)
     }
 
@@ -134,8 +246,8 @@ impl EguiView {
 
     fn new_workspace(&mut self) {
 	// Make the workspace
-	let analysis_engine = match self.new_workspace_setup.analysis_engine {
-	    Some(EngineType::KataGo) => match self.katago_installer.make_analysis_engine() {
+	let mut analysis_engine = match &self.new_workspace_setup.analysis_engine {
+	    Some(engine_type) => match self.make_engine(engine_type, EngineSlot::Analysis) {
 		Ok(engine) => Some(engine),
 		Err(s) => {
 		    println!("Analysis engine couldn't be created: {s}");
@@ -145,8 +257,8 @@ impl EguiView {
 	    None => None,
 	};
 	
-	let human_engine = match self.new_workspace_setup.game_mode {
-	    GameMode::HumanVsComputer(_turn) => match self.katago_installer.make_human_engine() {
+	let mut human_engine = match self.new_workspace_setup.game_mode {
+	    GameMode::HumanVsComputer(_turn) => match self.make_engine(&self.new_workspace_setup.computer_engine, EngineSlot::Human) {
 		Ok(engine) => Some(engine),
 		Err(s) => {
 		    println!("Human engine couldn't be created: {s}");
@@ -162,6 +274,14 @@ impl EguiView {
 	if let Some(_) = human_engine {
 	    println!("Human engine has been created.");
 	}
+
+	if self.new_workspace_setup.record_transcripts {
+	    for engine in [&mut analysis_engine, &mut human_engine].into_iter().flatten() {
+		if let Err(s) = engine.record_transcript(&self.transcript_dir) {
+		    println!("GTP transcript couldn't be created: {s}");
+		}
+	    }
+	}
 	
 	let model = Model::make_model(self.new_workspace_setup.board_size, analysis_engine, human_engine);
 	self.new_workspace_setup.count += 1;
@@ -178,6 +298,8 @@ impl EguiView {
 	    white_area_score: 0,
 	    new_black_captures: 0,
 	    new_white_captures: 0,
+	    restart_crashed_engines: false,
+	    show_engine_log: false,
 	};
 
 	// If computer is black, make the first move.
@@ -198,6 +320,32 @@ impl EguiView {
 	}
     }
 
+    fn make_engine(&self, engine_type: &EngineType, slot: EngineSlot) -> Result<Box<dyn GameEngine>, String> {
+	let setup = &self.new_workspace_setup;
+	Ok(match (engine_type, slot) {
+	    (EngineType::KataGo, EngineSlot::Analysis) => Box::new(self.katago_installer.make_analysis_engine(setup.analysis_network.as_deref())?),
+	    (EngineType::KataGo, EngineSlot::Human) => Box::new(self.katago_installer.make_human_engine(setup.computer_network.as_deref())?),
+	    (EngineType::Custom(name), _) => {
+		Box::new(self.engine_config.get_engine(name)
+		    .ok_or(format!("Engine {name} is not in the engine config!"))?
+		    .make_engine()?)
+	    },
+	    (EngineType::Remote, _) => {
+		let address = match slot {
+		    EngineSlot::Analysis => setup.analysis_address.trim(),
+		    EngineSlot::Human => setup.computer_address.trim(),
+		};
+		Box::new(SocketEngine::connect(address).map_err(|e| format!("Cannot connect to {address}: {e}"))?)
+	    },
+	})
+    }
+
+    fn save_engine_config(&self) {
+	if let Err(s) = self.engine_config.save(&self.engine_config_path) {
+	    println!("{s}");
+	}
+    }
+
     fn clone_workspace(&mut self) {
 	if let Some(n) = self.wspc {
 	    let w = &self.workspaces[n];
@@ -240,6 +388,37 @@ impl EguiView {
 	self.get_workspace_mut().map(|w| &mut w.model)
     }
 
+    fn restart_crashed_engines(&mut self) {
+	for w in self.workspaces.iter_mut() {
+	    if !w.restart_crashed_engines {
+		continue;
+	    }
+	    for slot in [EngineSlot::Analysis, EngineSlot::Human] {
+		if let Some(status) = w.model.engine_exit_status(slot) {
+		    println!("{slot:?} engine of workspace {} exited with {status}. Restarting.", w.name);
+		    if let Err(s) = w.model.restart_engine(slot) {
+			// Don't keep relaunching an engine that cannot start.
+			println!("Model restart_engine unsuccessful! {s}");
+			w.restart_crashed_engines = false;
+		    }
+		}
+	    }
+	}
+    }
+
+    // Takes the results of finished analyses and keeps redrawing while
+    // analyses run.
+    fn poll_analyses(&mut self, ctx: &egui::Context) {
+	for w in self.workspaces.iter_mut() {
+	    if let Err(s) = w.model.poll_analysis() {
+		println!("Model analysis unsuccessful! {s}");
+	    }
+	    if w.model.is_analyzing() {
+		ctx.request_repaint_after(Duration::from_millis(100));
+	    }
+	}
+    }
+
     fn run_egui(mut self) {
 	let options = eframe::NativeOptions {
             viewport: egui::ViewportBuilder::default().with_inner_size([640.0, 480.0]),
@@ -248,11 +427,14 @@ impl EguiView {
 
 	// Moving self to the following closure.
 	eframe::run_simple_native("Go", options, move |ctx, _frame| {
+	    self.restart_crashed_engines();
+	    self.poll_analyses(ctx);
 	    self.draw_top_panel(ctx);
 	    match self.mode {
 		ViewMode::Workspace => {
 		    if let Some(_) = self.wspc {
 			self.draw_workspace_side_panel(ctx);
+			self.draw_engine_log_bottom_panel(ctx);
 			self.draw_workspace_central_panel(ctx);
 		    }
 		},
@@ -340,6 +522,39 @@ impl EguiView {
 	    }
 	    ui.separator();
 
+	    // Engine status
+	    if let Some(w) = self.get_workspace_mut() {
+		let mut has_engine = false;
+		for (slot, name) in [(EngineSlot::Analysis, "Analysis engine"), (EngineSlot::Human, "Computer engine")] {
+		    if !w.model.has_engine(slot) {
+			continue;
+		    }
+		    has_engine = true;
+		    match w.model.engine_exit_status(slot) {
+			None => {
+			    ui.label(format!("{name}: running"));
+			},
+			Some(status) => {
+			    ui.label(format!("{name}: exited ({status})"));
+			    if ui.button(format!("Restart {}", name.to_lowercase())).clicked() {
+				if let Err(s) = w.model.restart_engine(slot) {
+				    println!("Model restart_engine unsuccessful! {s}");
+				}
+			    }
+			},
+		    }
+		}
+		if let Some(s) = w.model.get_last_desync() {
+		    ui.colored_label(egui::Color32::from_rgb(180, 0, 0), "Engine board was repaired.")
+			.on_hover_text(s);
+		}
+		if has_engine {
+		    ui.checkbox(&mut w.restart_crashed_engines, "Restart crashed engines");
+		    ui.checkbox(&mut w.show_engine_log, "Show engine log");
+		    ui.separator();
+		}
+	    }
+
 	    // Widgets specific to setup mode
 	    if let Some(w) = self.get_workspace() {
 		if let WorkspaceMode::Setup = w.mode {
@@ -446,11 +661,57 @@ impl EguiView {
 			ui.label(format!("White area score: {}", w.white_area_score));
 		    }
 		    ui.separator();
+
+		    if let Some(model) = self.get_model_mut() {
+			if model.has_engine(EngineSlot::Analysis) {
+			    // Allowed until the engine turns out to lack both.
+			    let can_analyze = [Capability::KataAnalyze, Capability::LzAnalyze].into_iter()
+				.any(|x| model.engine_has_capability(EngineSlot::Analysis, x) != Some(false));
+			    if model.is_analyzing() {
+				ui.horizontal(|ui| {
+				    ui.spinner();
+				    ui.label("Analyzing...");
+				});
+			    } else {
+				let button = ui.add_enabled(can_analyze, egui::Button::new("Analyze"))
+				    .on_disabled_hover_text("Engine supports neither kata-analyze nor lz-analyze.");
+				if button.clicked() {
+				    if let Err(s) = model.start_analysis(Duration::from_secs(1)) {
+					println!("Model start_analysis unsuccessful! {s}");
+				    }
+				}
+			    }
+			    ui.label("Black winrate:");
+			    draw_winrate_graph(ui, &model.get_black_winrates(), model.get_move_count());
+			    ui.separator();
+			}
+		    }
 		}
 	    }
 	});
     }
 
+    fn draw_engine_log_bottom_panel(&mut self, ctx: &egui::Context) {
+	let Some(w) = self.get_workspace() else { return; };
+	if !w.show_engine_log {
+	    return;
+	}
+
+	egui::TopBottomPanel::bottom("engine_log_panel").resizable(true).show(ctx, |ui| {
+	    egui::ScrollArea::vertical().stick_to_bottom(true).show(ui, |ui| {
+		for (slot, name) in [(EngineSlot::Analysis, "Analysis engine"), (EngineSlot::Human, "Computer engine")] {
+		    if !w.model.has_engine(slot) {
+			continue;
+		    }
+		    ui.label(egui::RichText::new(name).strong());
+		    for line in w.model.get_engine_log(slot) {
+			ui.label(egui::RichText::new(line).monospace());
+		    }
+		}
+	    });
+	});
+    }
+
     fn draw_workspace_central_panel(&mut self, ctx: &egui::Context) {
 	egui::CentralPanel::default().show(ctx, |ui| {
 	    let painter = ui.painter();
@@ -551,6 +812,25 @@ impl EguiView {
 		    painter.circle_filled(center, stone_radius / 5.0, egui::Color32::from_rgb(180, 0, 0));
 		}
 
+		// Draw analysis overlay
+		if let Some(WorkspaceMode::Analysis) = self.get_workspace().map(|w| w.mode) {
+		    for m in model.get_analysis() {
+			let Some((x, y)) = m.point else { continue; };
+			let x = square_b.left() + cell_size / 2.0 + x as f32 * cell_size;
+			let y = square_b.top() + cell_size / 2.0 + y as f32 * cell_size;
+			let center = egui::Pos2::new(x, y);
+
+			let color = if m.order == 0 {
+			    egui::Color32::from_rgba_unmultiplied(0, 120, 255, 180)
+			} else {
+			    egui::Color32::from_rgba_unmultiplied(0, 180, 0, 120)
+			};
+			painter.circle_filled(center, stone_radius, color);
+			painter.text(center, egui::Align2::CENTER_CENTER, format!("{:.0}", m.winrate * 100.0),
+				     egui::FontId::proportional(cell_size * 0.4), egui::Color32::BLACK);
+		    }
+		}
+
 		// Draw area on the right of the board
 		let turn = model.get_turn();
 		let turn_stone_center = egui::Pos2::new(square_a.right() + cell_size, canvas.top() + canvas_height / 2.0);
@@ -657,36 +937,64 @@ impl EguiView {
 	egui::CentralPanel::default().show(ctx, |ui| {
 	    ui.add(egui::Slider::new(&mut self.new_workspace_setup.board_size, 2..=25).text("Board size"));
 
-	    let game_mode = &mut self.new_workspace_setup.game_mode;
-	    ui.label("Game mode:");
-	    ui.radio_value(game_mode, GameMode::HumanVsHuman, "Human vs. human");
-	    
-	    let engine_installed = if let Ok(status) = self.katago_installer_status.try_lock() {
+	    let katago_installed = if let Ok(status) = self.katago_installer_status.try_lock() {
 		status.is_installed == Some(true)
 	    } else {
 		false
 	    };
-	    if engine_installed {
-		ui.radio_value(game_mode, GameMode::HumanVsComputer(Turn::Black), "Human (black) vs. computer (white)");
-		ui.radio_value(game_mode, GameMode::HumanVsComputer(Turn::White), "Human (white) vs. computer (black)");
-	    } else {
-		ui.add_enabled_ui(false, |ui| {
-		    ui.radio_value(game_mode, GameMode::HumanVsComputer(Turn::Black), "Human (black) vs. computer (white)");
-		    ui.radio_value(game_mode, GameMode::HumanVsComputer(Turn::White), "Human (white) vs. computer (black)");
-		});		    
+	    let custom_engines: Vec<_> = self.engine_config.engines.iter().map(|x| x.name.clone()).collect();
+	    let networks = self.katago_installer.get_networks().list().unwrap_or_default();
+
+	    let game_mode = &mut self.new_workspace_setup.game_mode;
+	    ui.label("Game mode:");
+	    ui.radio_value(game_mode, GameMode::HumanVsHuman, "Human vs. human");
+	    // A remote engine is always a choice.
+	    ui.radio_value(game_mode, GameMode::HumanVsComputer(Turn::Black), "Human (black) vs. computer (white)");
+	    ui.radio_value(game_mode, GameMode::HumanVsComputer(Turn::White), "Human (white) vs. computer (black)");
+
+	    if let GameMode::HumanVsComputer(_) = self.new_workspace_setup.game_mode {
+		let computer_engine = &mut self.new_workspace_setup.computer_engine;
+		ui.label("Computer engine:");
+		ui.add_enabled_ui(katago_installed, |ui| {
+		    ui.radio_value(computer_engine, EngineType::KataGo, "KataGo");
+		});
+		for name in &custom_engines {
+		    ui.radio_value(computer_engine, EngineType::Custom(name.clone()), name);
+		}
+		ui.radio_value(computer_engine, EngineType::Remote, "Remote");
+		match self.new_workspace_setup.computer_engine {
+		    EngineType::KataGo => {
+			draw_network_choice(ui, "Computer network", &mut self.new_workspace_setup.computer_network, &networks);
+		    },
+		    EngineType::Remote => {
+			draw_address_field(ui, &mut self.new_workspace_setup.computer_address);
+		    },
+		    EngineType::Custom(_) => (),
+		}
 	    }
 
 	    let analysis_engine = &mut self.new_workspace_setup.analysis_engine;
 	    ui.label("Analysis engine:");
 	    ui.radio_value(analysis_engine, None, "None");
-	    if engine_installed {
+	    ui.add_enabled_ui(katago_installed, |ui| {
 		ui.radio_value(analysis_engine, Some(EngineType::KataGo), "KataGo");
-	    } else {
-		ui.add_enabled_ui(false, |ui| {
-		    ui.radio_value(analysis_engine, Some(EngineType::KataGo), "KataGo");
-		});		    
+	    });
+	    for name in &custom_engines {
+		ui.radio_value(analysis_engine, Some(EngineType::Custom(name.clone())), name);
+	    }
+	    ui.radio_value(analysis_engine, Some(EngineType::Remote), "Remote");
+	    match self.new_workspace_setup.analysis_engine {
+		Some(EngineType::KataGo) => {
+		    draw_network_choice(ui, "Analysis network", &mut self.new_workspace_setup.analysis_network, &networks);
+		},
+		Some(EngineType::Remote) => {
+		    draw_address_field(ui, &mut self.new_workspace_setup.analysis_address);
+		},
+		_ => (),
 	    }
 
+	    ui.checkbox(&mut self.new_workspace_setup.record_transcripts, "Record GTP transcripts");
+
 	    ui.horizontal(|ui| {
 		if ui.button("Cancel").clicked() {
 		    self.mode = ViewMode::Workspace;
@@ -701,8 +1009,6 @@ impl EguiView {
 
     fn draw_install_engine_central_panel(&mut self, ctx: &egui::Context) {
 	egui::CentralPanel::default().show(ctx, |ui| {
-	    // TODO: Custom engine setup via command, save the command to config file
-
 	    let mutex = self.katago_installer_status.clone();
 
 	    // Status information
@@ -731,22 +1037,68 @@ impl EguiView {
 		tuning_status_str.push_str("not known");
 		testing_status_str.push_str("not known");
 	    }
+	    let backend_str = match self.katago_installer.get_backend() {
+		Some(backend) => backend.get_description(),
+		None => "none",
+	    };
+	    ui.label(format!("KataGo backend: {backend_str}"));
+	    ui.label(format!("KataGo version: {}", self.katago_installer.get_version()));
 	    ui.label(installation_status_str);
 	    ui.label(tuning_status_str);
 	    ui.label(testing_status_str);
 
+	    // Backend selection
+	    let idle = mutex.try_lock().is_ok();
+	    ui.add_enabled_ui(idle && self.katago_installer.get_backend().is_some(), |ui| {
+		let detected = self.detected_backends.clone();
+		let describe = |backend: &Backend| if detected.contains(backend) {
+		    backend.get_description().to_string()
+		} else {
+		    format!("{} (not detected)", backend.get_description())
+		};
+		let selected_str = match &self.katago_backend_choice {
+		    Some(backend) => describe(backend),
+		    None => "Auto".to_string(),
+		};
+		let previous_choice = self.katago_backend_choice;
+		egui::ComboBox::from_label("Backend to install")
+		    .selected_text(selected_str)
+		    .show_ui(ui, |ui| {
+			ui.selectable_value(&mut self.katago_backend_choice, None, "Auto");
+			for backend in Backend::ALL {
+			    ui.selectable_value(&mut self.katago_backend_choice, Some(backend), describe(&backend));
+			}
+		    });
+		if self.katago_backend_choice != previous_choice {
+		    if let Some(backend) = self.katago_backend_choice {
+			self.do_katago_installer_op(move |installer, status, _kill_signal_rx| {
+			    installer.set_backend(backend)?;
+			    status.is_installed = Some(installer.is_installed());
+			    status.is_tuned = Some(installer.is_tuned());
+			    status.is_operational = None;
+			    Ok(())
+			});
+		    }
+		}
+	    });
+
 	    // Buttons
 	    ui.horizontal(|ui| {
 		if let Ok(_status) = mutex.try_lock() {
 		    // Install button
 		    if ui.button("(Re)Install").clicked() {
-			self.do_katago_installer_op(|installer, status, kill_signal_rx| {
-			    match installer.install(kill_signal_rx) {
+			let choice = self.katago_backend_choice;
+			self.do_katago_installer_op(move |installer, status, kill_signal_rx| {
+			    let r = match choice {
+				Some(_) => installer.install(&kill_signal_rx),
+				None => installer.install_auto(kill_signal_rx).map(|_| ()),
+			    };
+			    match r {
 				Ok(()) => {
 				    status.is_installed = Some(true);
 				},
 				Err(s) => {
-				    status.is_installed = Some(false);
+				    status.is_installed = Some(installer.is_installed());
 				    println!("KataGo installation unsuccessful! {s}");
 				    return Err(format!("KataGo installation unsuccessful! {s}"));
 				}
@@ -758,11 +1110,11 @@ impl EguiView {
 		    
 		    // Test button
 		    if ui.button("Test").clicked() {
-			self.do_katago_installer_op(|installer, status, _kill_signal_rx| {
+			self.do_katago_installer_op(|installer, status, kill_signal_rx| {
 			    // Also check if it is tuned
 			    status.is_tuned = Some(installer.is_tuned());
 			    
-			    match installer.test() {
+			    match installer.test(&kill_signal_rx) {
 				Ok(version) => {
 				    status.is_operational = Some(true);
 				    println!("KataGo testing successful.");
@@ -781,12 +1133,304 @@ impl EguiView {
 		    // Deactivated buttons and spinner.
 		    if ui.add_enabled(false, egui::Button::new("Re(Install)")).clicked() {}
 		    if ui.add_enabled(false, egui::Button::new("Test")).clicked() {}
+		    if ui.button("Cancel").clicked() {
+			if let Some(handle) = &self.katago_installer_handle {
+			    handle.send_kill_signal();
+			}
+		    }
 		    ui.add(egui::Spinner::new());
 		}
 	    });
+
+	    // Offline installation
+	    ui.label("Install from local files or directories, one per line:");
+	    ui.text_edit_multiline(&mut self.offline_install_sources);
+	    let idle = mutex.try_lock().is_ok();
+	    if ui.add_enabled(idle, egui::Button::new("Install from local files")).clicked() {
+		let sources: Vec<PathBuf> = self.offline_install_sources.lines()
+		    .map(|x| x.trim())
+		    .filter(|x| !x.is_empty())
+		    .map(PathBuf::from)
+		    .collect();
+		self.do_katago_installer_op(move |installer, status, kill_signal_rx| {
+		    let r = installer.install_offline(&sources, &kill_signal_rx);
+		    status.is_installed = Some(installer.is_installed());
+		    status.is_tuned = Some(installer.is_tuned());
+		    r.map_err(|s| format!("KataGo offline installation unsuccessful! {s}"))
+		});
+	    }
+
+	    if let Some(progress) = self.katago_installer.get_progress() {
+		ui.label(&progress.step);
+		if let Some(download) = &progress.download {
+		    draw_download_progress(ui, download);
+		}
+		ctx.request_repaint_after(Duration::from_millis(100));
+	    }
+
+	    ui.separator();
+	    self.draw_installed_versions(ui);
+	    ui.separator();
+	    self.draw_external_katago(ui);
+	    ui.separator();
+	    self.draw_networks(ui);
+	    ui.separator();
+	    self.draw_katago_config(ui);
+	    ui.separator();
+	    self.draw_custom_engines(ui);
 	});
     }
 
+    fn draw_installed_versions(&mut self, ui: &mut egui::Ui) {
+	ui.label(egui::RichText::new("Installed versions").strong());
+	let idle = self.katago_installer_status.try_lock().is_ok();
+	if let Some(version) = self.katago_installer.get_update() {
+	    ui.horizontal(|ui| {
+		ui.label(format!("KataGo {version} is available."));
+		if ui.add_enabled(idle, egui::Button::new(format!("Upgrade to {version}"))).clicked() {
+		    self.do_katago_installer_op(|installer, status, kill_signal_rx| {
+			let r = installer.upgrade(&kill_signal_rx);
+			status.is_installed = Some(installer.is_installed());
+			status.is_tuned = Some(installer.is_tuned());
+			status.is_operational = Some(r.is_ok());
+			r.map_err(|s| format!("KataGo upgrade unsuccessful! {s}"))
+		    });
+		}
+	    });
+	}
+	let manifest = self.katago_installer.get_manifest().unwrap_or_else(|s| {
+	    ui.label(s);
+	    InstallManifest::default()
+	});
+	for record in manifest.installs {
+	    ui.horizontal(|ui| {
+		match &record.exe {
+		    Some(exe) => { ui.label(format!("{} {} ({}), added {}", record.version, record.backend, exe.display(), record.get_date())); },
+		    None => { ui.label(format!("{} {}, installed {}", record.version, record.backend, record.get_date())); },
+		}
+		if record.active {
+		    ui.label("(active)");
+		} else if ui.add_enabled(idle, egui::Button::new("Activate")).clicked() {
+		    self.do_katago_installer_op(move |installer, status, _kill_signal_rx| {
+			installer.activate(&record.version, &record.backend)?;
+			status.is_installed = Some(installer.is_installed());
+			status.is_tuned = Some(installer.is_tuned());
+			status.is_operational = None;
+			Ok(())
+		    });
+		}
+	    });
+	}
+    }
+
+    fn draw_external_katago(&mut self, ui: &mut egui::Ui) {
+	ui.label(egui::RichText::new("External KataGo").strong());
+	let idle = self.katago_installer_status.try_lock().is_ok();
+	ui.horizontal(|ui| {
+	    if self.external_katago.is_empty() {
+		ui.label("No KataGo found on PATH.");
+	    }
+	    for exe in &self.external_katago {
+		if ui.button(exe.display().to_string()).clicked() {
+		    self.external_exe = exe.display().to_string();
+		}
+	    }
+	    if ui.button("Search again").clicked() {
+		self.external_katago = self.katago_installer.find_external();
+	    }
+	});
+	egui::Grid::new("external_katago_form").num_columns(2).show(ui, |ui| {
+	    ui.label("Executable:");
+	    ui.text_edit_singleline(&mut self.external_exe);
+	    ui.end_row();
+	    ui.label("Networks, one per line:");
+	    ui.text_edit_multiline(&mut self.external_models);
+	    ui.end_row();
+	});
+	if ui.add_enabled(idle, egui::Button::new("Test and use")).clicked() {
+	    let exe = PathBuf::from(self.external_exe.trim());
+	    let models: Vec<PathBuf> = self.external_models.lines()
+		.map(|x| x.trim())
+		.filter(|x| !x.is_empty())
+		.map(PathBuf::from)
+		.collect();
+	    self.do_katago_installer_op(move |installer, status, kill_signal_rx| {
+		let r = installer.adopt_external(&exe, &models, &kill_signal_rx);
+		status.is_installed = Some(installer.is_installed());
+		status.is_tuned = Some(installer.is_tuned());
+		status.is_operational = Some(r.is_ok());
+		let version = r.map_err(|s| format!("External KataGo doesn't work! {s}"))?;
+		println!("Using external KataGo {version}.");
+		Ok(())
+	    });
+	}
+    }
+
+    fn draw_networks(&mut self, ui: &mut egui::Ui) {
+	ui.label(egui::RichText::new("KataGo networks").strong());
+	let idle = self.katago_installer_status.try_lock().is_ok();
+	let networks = self.katago_installer.get_networks().list().unwrap_or_else(|s| {
+	    ui.label(s);
+	    Vec::new()
+	});
+	let in_use = self.katago_installer.get_networks_in_use();
+	for network in networks {
+	    ui.horizontal(|ui| {
+		ui.label(format!("{} ({})", network.file_name, network.get_description()));
+		let used = in_use.contains(&network.file_name);
+		let mut button = ui.add_enabled(idle && !used, egui::Button::new("Remove"));
+		if used {
+		    button = button.on_disabled_hover_text("Used by the active KataGo install.");
+		}
+		if button.clicked() {
+		    self.do_katago_installer_op(move |installer, status, _kill_signal_rx| {
+			installer.remove_network(&network.file_name)?;
+			status.is_installed = Some(installer.is_installed());
+			status.is_operational = None;
+			Ok(())
+		    });
+		}
+	    });
+	}
+	ui.horizontal(|ui| {
+	    ui.label("URL or file:");
+	    ui.text_edit_singleline(&mut self.network_source);
+	    if ui.add_enabled(idle, egui::Button::new("Add network")).clicked() {
+		let source = self.network_source.trim().to_string();
+		self.network_source.clear();
+		self.do_katago_installer_op(move |installer, status, kill_signal_rx| {
+		    let info = installer.add_network(&source, &kill_signal_rx)?;
+		    println!("Added network {}.", info.file_name);
+		    status.is_installed = Some(installer.is_installed());
+		    Ok(())
+		});
+	    }
+	});
+    }
+
+    fn draw_katago_config(&mut self, ui: &mut egui::Ui) {
+	ui.label(egui::RichText::new("KataGo settings").strong());
+	let form = &mut self.katago_config_form;
+	let previous_kind = form.kind;
+	egui::ComboBox::from_label("Config")
+	    .selected_text(form.kind.get_name())
+	    .show_ui(ui, |ui| {
+		for kind in ConfigKind::ALL {
+		    ui.selectable_value(&mut form.kind, kind, kind.get_name());
+		}
+	    });
+	if form.kind != previous_kind {
+	    form.values = None;
+	}
+	if form.values.is_none() {
+	    match self.katago_installer.load_config(form.kind) {
+		Ok(config) => {
+		    form.values = Some(COMMON_SETTINGS.iter()
+			.map(|(key, _)| (key.to_string(), config.get(key).unwrap_or_default()))
+			.collect());
+		},
+		Err(s) => {
+		    ui.label(s);
+		    return;
+		},
+	    }
+	}
+
+	let values = form.values.as_mut().unwrap();
+	egui::Grid::new("katago_config_form").num_columns(2).show(ui, |ui| {
+	    for (key, description) in COMMON_SETTINGS {
+		ui.label(description).on_hover_text(key);
+		ui.text_edit_singleline(values.entry(key.to_string()).or_default());
+		ui.end_row();
+	    }
+	});
+	ui.label(format!("Other settings are in {:?}.", self.katago_installer.get_config_path(form.kind)));
+	let mut reload = false;
+	ui.horizontal(|ui| {
+	    // Empty fields remove the setting, leaving it to KataGo.
+	    if ui.button("Save").clicked() {
+		let r = self.katago_installer.load_config(form.kind).and_then(|mut config| {
+		    for (key, value) in values.iter() {
+			config.set(key, value);
+		    }
+		    self.katago_installer.save_config(form.kind, &config)
+		});
+		if let Err(s) = r {
+		    println!("KataGo config couldn't be saved: {s}");
+		}
+	    }
+	    reload = ui.button("Reload").clicked();
+	});
+	if reload {
+	    form.values = None;
+	}
+    }
+
+    fn draw_custom_engines(&mut self, ui: &mut egui::Ui) {
+	ui.label(egui::RichText::new("Custom engines").strong());
+	let mut remove = None;
+	for engine in &self.engine_config.engines {
+	    ui.horizontal(|ui| {
+		ui.label(format!("{}: {}", engine.name, engine.command));
+		if ui.button("Test").clicked() {
+		    let engine = engine.clone();
+		    let status = self.custom_engine_status.clone();
+		    status.lock().unwrap().insert(engine.name.clone(), "testing...".to_string());
+		    self.smart_handles.push(smart_thread::spawn(move |_kill_signal_rx| {
+			let s = match engine.test() {
+			    Ok(s) => format!("successful ({s})"),
+			    Err(s) => format!("unsuccessful: {s}"),
+			};
+			status.lock().unwrap().insert(engine.name.clone(), s);
+			Ok(())
+		    }));
+		}
+		if ui.button("Remove").clicked() {
+		    remove = Some(engine.name.clone());
+		}
+		if let Some(s) = self.custom_engine_status.lock().unwrap().get(&engine.name) {
+		    ui.label(format!("Test {s}"));
+		}
+	    });
+	}
+	if let Some(name) = remove {
+	    self.engine_config.remove_engine(&name);
+	    self.save_engine_config();
+	}
+
+	let form = &mut self.custom_engine_form;
+	egui::Grid::new("custom_engine_form").num_columns(2).show(ui, |ui| {
+	    ui.label("Name:");
+	    ui.text_edit_singleline(&mut form.name);
+	    ui.end_row();
+	    ui.label("Command:");
+	    ui.text_edit_singleline(&mut form.command);
+	    ui.end_row();
+	    ui.label("Working directory:");
+	    ui.text_edit_singleline(&mut form.working_dir);
+	    ui.end_row();
+	    ui.label("Environment (KEY=VALUE):");
+	    ui.text_edit_multiline(&mut form.env);
+	    ui.end_row();
+	    ui.label("Init script:");
+	    ui.text_edit_singleline(&mut form.init_script);
+	    ui.end_row();
+	    ui.label("Init commands:");
+	    ui.text_edit_multiline(&mut form.init_commands);
+	    ui.end_row();
+	});
+	if ui.button("Add engine").clicked() {
+	    let engine = form.to_custom_engine();
+	    match self.engine_config.add_engine(engine) {
+		Ok(()) => {
+		    self.custom_engine_form = CustomEngineForm::default();
+		    self.save_engine_config();
+		},
+		Err(s) => { println!("Custom engine couldn't be added: {s}"); },
+	    }
+	}
+    }
+
     fn do_katago_installer_op<F>(&mut self, func: F)
     where F: FnOnce(&KataGoInstaller, &mut KataGoInstallerStatus, Receiver<()>) -> Result<(), String> + std::marker::Send + 'static {
 	// Move a copy of mutex and installer to the new
@@ -803,7 +1447,71 @@ impl EguiView {
 	    }
 	    r
 	});
-	self.smart_handles.push(handle);
+	self.katago_installer_handle = Some(handle);
+    }
+}
+
+
+fn draw_network_choice(ui: &mut egui::Ui, label: &str, choice: &mut Option<String>, networks: &[NetworkInfo]) {
+    let selected = choice.clone().unwrap_or("Default".to_string());
+    egui::ComboBox::from_label(label)
+	.selected_text(selected)
+	.show_ui(ui, |ui| {
+	    ui.selectable_value(choice, None, "Default");
+	    for network in networks {
+		ui.selectable_value(choice, Some(network.file_name.clone()), format!("{} ({})", network.file_name, network.get_description()));
+	    }
+	});
+}
+
+
+fn draw_address_field(ui: &mut egui::Ui, address: &mut String) {
+    ui.horizontal(|ui| {
+	ui.label("Address:");
+	ui.text_edit_singleline(address)
+	    .on_hover_text("host:port, or unix:<path> for a Unix socket");
+    });
+}
+
+
+fn draw_download_progress(ui: &mut egui::Ui, download: &DownloadProgress) {
+    const MB: f64 = 1024.0 * 1024.0;
+    let mut text = match download.total {
+	Some(total) => format!("{:.1} / {:.1} MB", download.done as f64 / MB, total as f64 / MB),
+	None => format!("{:.1} MB", download.done as f64 / MB),
+    };
+    text.push_str(&format!(", {:.1} MB/s", download.rate / MB));
+    if let Some(eta) = download.get_eta() {
+	text.push_str(&format!(", {}s left", eta.as_secs()));
+    }
+    let bar = match download.get_fraction() {
+	Some(fraction) => egui::ProgressBar::new(fraction),
+	None => egui::ProgressBar::new(0.0).animate(true),
+    };
+    ui.add(bar.text(text));
+}
+
+
+// Line graph of black's winrate over the moves of the game.
+fn draw_winrate_graph(ui: &mut egui::Ui, winrates: &[(usize, f32)], move_count: usize) {
+    let (rect, _) = ui.allocate_exact_size(egui::Vec2::new(ui.available_width(), 80.0), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, egui::Color32::from_rgb(240, 240, 240));
+    painter.line_segment([rect.left_center(), rect.right_center()], egui::Stroke::new(1.0, egui::Color32::GRAY));
+
+    let last = winrates.last().map_or(0, |(n, _)| *n).max(move_count).max(1);
+    let to_pos = |n: usize, winrate: f32| egui::Pos2::new(
+	rect.left() + rect.width() * n as f32 / last as f32,
+	rect.bottom() - rect.height() * winrate,
+    );
+
+    let x = to_pos(move_count, 0.0).x;
+    painter.line_segment([egui::Pos2::new(x, rect.top()), egui::Pos2::new(x, rect.bottom())], egui::Stroke::new(1.0, egui::Color32::from_rgb(180, 0, 0)));
+
+    let points: Vec<_> = winrates.iter().map(|(n, w)| to_pos(*n, *w)).collect();
+    painter.add(egui::Shape::line(points.clone(), egui::Stroke::new(1.5, egui::Color32::BLACK)));
+    for p in points {
+	painter.circle_filled(p, 2.0, egui::Color32::BLACK);
     }
 }
 
@@ -0,0 +1,148 @@
+// GTP engines that users register besides the installed KataGo. They
+// are kept in a TOML file under the app directory:
+//
+//   [[engines]]
+//   name = "GNU Go"
+//   command = "gnugo --mode gtp"
+//   working_dir = "/home/me/gnugo"   # Optional.
+//   init_script = "/home/me/init.gtp" # Optional, GTP commands sent after starting.
+//   init_commands = ["level 5"]      # Optional, sent after the script.
+//
+//   [engines.env]                    # Optional.
+//   OMP_NUM_THREADS = "2"
+
+use crate::child_process_engine::ChildProcessEngine;
+use crate::gtp::GTPEngineMinimal;
+use crate::smart_child::LaunchSpec;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+
+pub const ENGINE_CONFIG_FILE: &str = "engines.toml";
+
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EngineConfig {
+    #[serde(default)]
+    pub engines: Vec<CustomEngine>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomEngine {
+    pub name: String,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub init_script: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub init_commands: Vec<String>,
+}
+
+
+impl EngineConfig {
+    // A missing file is an empty configuration.
+    pub fn load(path: &Path) -> Result<Self, String> {
+	if !path.exists() {
+	    return Ok(Self::default());
+	}
+	let content = fs::read_to_string(path).map_err(|e| format!("Cannot read engine config {path:?}: {e}"))?;
+	toml::from_str(&content).map_err(|e| format!("Cannot parse engine config {path:?}: {e}"))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+	if let Some(dir) = path.parent() {
+	    fs::create_dir_all(dir).map_err(|e| format!("Cannot create directory {dir:?}: {e}"))?;
+	}
+	let content = toml::to_string(self).map_err(|e| format!("Cannot serialize engine config: {e}"))?;
+	fs::write(path, content).map_err(|e| format!("Cannot write engine config {path:?}: {e}"))
+    }
+
+    pub fn get_engine(&self, name: &str) -> Option<&CustomEngine> {
+	self.engines.iter().find(|x| x.name == name)
+    }
+
+    // Adds the engine, replacing an engine with the same name.
+    pub fn add_engine(&mut self, engine: CustomEngine) -> Result<(), String> {
+	if engine.name.trim().is_empty() || engine.command.trim().is_empty() {
+	    return Err("Engine name and command cannot be empty!".to_string());
+	}
+	match self.engines.iter_mut().find(|x| x.name == engine.name) {
+	    Some(x) => { *x = engine; },
+	    None => { self.engines.push(engine); },
+	}
+	Ok(())
+    }
+
+    pub fn remove_engine(&mut self, name: &str) {
+	self.engines.retain(|x| x.name != name);
+    }
+}
+
+
+impl CustomEngine {
+    pub fn make_engine(&self) -> Result<ChildProcessEngine, String> {
+	let mut spec = LaunchSpec::from_command_str(&self.command)?;
+	spec.working_dir = self.working_dir.clone();
+	spec.env = self.env.clone();
+	if let Some(path) = &self.init_script {
+	    spec.load_init_script(path)?;
+	}
+	spec.init_script.extend(self.init_commands.iter().cloned());
+	ChildProcessEngine::from_spec(spec).map_err(|e| format!("Cannot start engine {}: {e}", self.name))
+    }
+
+    // Starts the engine and returns its name and version.
+    pub fn test(&self) -> Result<String, String> {
+	let mut engine = self.make_engine()?;
+	let name = engine.name()?;
+	let version = engine.version()?;
+	engine.quit()?;
+	Ok(format!("{name} {version}"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_engine::fake_engine_command;
+    use std::env;
+
+    #[test]
+    fn config() {
+	let path = env::temp_dir().join(format!("go-game-engines-{}", std::process::id())).join(ENGINE_CONFIG_FILE);
+	assert_eq!(EngineConfig::load(&path).unwrap(), EngineConfig::default());
+
+	let script = env::temp_dir().join(format!("go-game-engine-script-{}", std::process::id()));
+	fs::write(&script, "# Fake accepts these.\nboardsize 9\n").unwrap();
+	let mut config = EngineConfig::default();
+	let mut engine = CustomEngine {
+	    name: "Fake".to_string(),
+	    command: fake_engine_command(""),
+	    working_dir: Some(env::temp_dir()),
+	    env: BTreeMap::from([("FAKE_LEVEL".to_string(), "3".to_string())]),
+	    init_script: Some(script.clone()),
+	    init_commands: vec!["boardsize 9".to_string()],
+	};
+	config.add_engine(engine.clone()).unwrap();
+	assert!(config.add_engine(CustomEngine::default()).is_err());
+	config.save(&path).unwrap();
+	let loaded = EngineConfig::load(&path).unwrap();
+	fs::remove_dir_all(path.parent().unwrap()).unwrap();
+	assert_eq!(loaded, config);
+
+	assert_eq!(loaded.get_engine("Fake").unwrap().test().unwrap(), "Fake 0.1");
+	engine.init_commands.push("boardsize 1".to_string());
+	assert!(engine.test().is_err());
+	fs::write(&script, "boardsize 1\n").unwrap();
+	engine.init_commands.clear();
+	assert!(engine.test().is_err());
+	fs::remove_file(&script).unwrap();
+	assert!(engine.test().unwrap_err().contains("Cannot read init script"));
+    }
+}
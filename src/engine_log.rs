@@ -0,0 +1,82 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+
+pub const ENGINE_LOG_CAPACITY: usize = 1000;
+
+
+// Bounded buffer of the last lines an engine wrote to its
+// log. Cloning shares the buffer, so the engine can write to it from
+// a thread while the view reads it.
+#[derive(Clone)]
+pub struct EngineLog {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+}
+
+
+impl EngineLog {
+    pub fn new(capacity: usize) -> Self {
+	Self {
+	    lines: Arc::new(Mutex::new(VecDeque::new())),
+	    capacity: capacity,
+	}
+    }
+
+    pub fn push(&self, line: String) {
+	let mut lines = self.lines.lock().unwrap();
+	if lines.len() == self.capacity {
+	    lines.pop_front();
+	}
+	lines.push_back(line);
+    }
+
+    pub fn get_lines(&self) -> Vec<String> {
+	self.lines.lock().unwrap().iter().cloned().collect()
+    }
+
+    // Last n lines joined into a single string.
+    pub fn tail(&self, n: usize) -> String {
+	let lines = self.lines.lock().unwrap();
+	let start = lines.len().saturating_sub(n);
+	lines.range(start..).cloned().collect::<Vec<_>>().join("\n")
+    }
+
+    // Reads lines from the stream until EOF on a new thread. Draining
+    // the stream also keeps the engine from stalling on a full pipe.
+    pub fn drain<R>(&self, stream: R) -> thread::JoinHandle<()>
+    where
+	R: Read + Send + 'static,
+    {
+	let log = self.clone();
+	thread::spawn(move || {
+	    let reader = BufReader::new(stream);
+	    for line in reader.split(b'\n') {
+		match line {
+		    Ok(line) => {
+			let line = String::from_utf8_lossy(&line);
+			log.push(line.trim_end_matches('\r').to_string());
+		    },
+		    Err(_) => { break; },
+		}
+	    }
+	})
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded() {
+	let log = EngineLog::new(3);
+	let handle = log.drain("a\nb\r\nc\nd\ne".as_bytes());
+	handle.join().unwrap();
+	assert_eq!(log.get_lines(), vec!["c", "d", "e"]);
+	assert_eq!(log.tail(2), "d\ne");
+    }
+}
@@ -0,0 +1,41 @@
+use crate::gtp::*;
+use crate::gtp_katago::KataGoGtpExtensions;
+use crate::engine_log::EngineLog;
+use crate::gtp_channel::GtpChannel;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::Duration;
+
+
+// Engine the model plays and analyzes with, whether it runs as a
+// child process or elsewhere behind a socket.
+pub trait GameEngine: GTPEngineMinimal + GTPEngineExtendedCorePlay + GTPEngineDebug + GTPEngineLeelaZero + KataGoGtpExtensions + Send {
+    // Commands the engine knows, None until query_capabilities.
+    fn get_capabilities(&self) -> Option<&Capabilities>;
+
+    // Asks list_commands on first use rather than on start, since an
+    // engine may not answer before it has loaded, e.g. KataGo loading
+    // its network or tuning OpenCL.
+    fn query_capabilities(&mut self) -> Result<&Capabilities, GtpError>;
+
+    // stderr of the engine, None if it isn't available.
+    fn get_log(&self) -> Option<&EngineLog> { None }
+
+    // Records the session to a new transcript file in dir.
+    fn record_transcript(&mut self, dir: &Path) -> Result<PathBuf, String>;
+
+    fn set_command_timeout(&mut self, timeout: Option<Duration>);
+
+    // Handle that shares the engine, e.g. for analysis that streams
+    // until it is interrupted. None for engines that answer one
+    // command at a time.
+    fn get_channel(&self) -> Option<GtpChannel> { None }
+
+    // Returns the exit status if the engine has exited, without
+    // blocking. None for engines that aren't processes.
+    fn exit_status(&mut self) -> Option<ExitStatus> { None }
+
+    // Starts over with an empty board, e.g. after a crash. It is the
+    // caller's responsibility to replay the position.
+    fn restart(&mut self) -> Result<(), GtpError>;
+}
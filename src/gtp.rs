@@ -2,138 +2,372 @@
 // https://www.lysator.liu.se/~gunnar/gtp/gtp2-spec-draft2/gtp2-spec.html
 
 use std::str;
+use std::collections::HashSet;
+use std::fmt;
+use std::error::Error;
+use crate::gtp_transcript::Transcript;
+use crate::gtp_channel::PendingResponse;
 
 //
 // GTP COMMANDS THAT CONTROLLER CALLS
 //
 
-pub trait GTPEngineMinimal {
-    fn protocol_version(&mut self) -> Result<u32, String>;
-    fn name(&mut self) -> Result<String, String>;
-    fn version(&mut self) -> Result<String, String>;
-    fn known_command(&mut self, command_name: &str) -> Result<bool, String>;
-    fn list_commands(&mut self) -> Result<Vec<String>, String>;
-    fn quit(self) -> Result<(), String>;
-    fn boardsize(&mut self, size: u32) -> Result<(), String>;
-    fn clear_board(&mut self) -> Result<(), String>;
-    fn komi(&mut self, new_komi: f32) -> Result<(), String>;
-    fn play(&mut self, gtp_move: GTPMove) -> Result<(), String>;
-    fn genmove(&mut self, color: Color) -> Result<GenMove, String>;
+// Commands are implemented on top of GTPEngineRaw::send_command, so
+// an engine only needs to implement GTPEngineRaw. Engines can
+// override a command when they need more than sending it, e.g. quit.
+
+pub trait GTPEngineMinimal: GTPEngineRaw {
+    fn protocol_version(&mut self) -> Result<u32, GtpError> {
+	let s = self.send_command("protocol_version")?;
+	s.parse::<u32>().map_err(|_| GtpError::Parse(format!("{} cannot be parsed into u32!", s)))
+    }
+
+    fn name(&mut self) -> Result<String, GtpError> {
+	self.send_command("name")
+    }
+
+    fn version(&mut self) -> Result<String, GtpError> {
+	self.send_command("version")
+    }
+
+    fn known_command(&mut self, command_name: &str) -> Result<bool, GtpError> {
+	if !is_single_token(command_name) {
+	    return Err(GtpError::Protocol(format!("command_name '{command_name}' is not single token!")));
+	}
+	let s = self.send_command(&format!("known_command {}", command_name))?;
+	if s == "true" {
+	    Ok(true)
+	} else if s == "false" {
+	    Ok(false)
+	} else {
+	    Err(GtpError::Parse(format!("command_name '{command_name}' is not 'true' or 'false'!")))
+	}
+    }
+
+    fn list_commands(&mut self) -> Result<Vec<String>, GtpError> {
+	let s = self.send_command("list_commands")?;
+	Ok(s.split_whitespace().map(|x| String::from(x)).collect())
+    }
+
+    fn quit(mut self) -> Result<(), GtpError> where Self: Sized {
+	self.send_command("quit")?;
+	Ok(())
+    }
+
+    fn boardsize(&mut self, size: u32) -> Result<(), GtpError> {
+	self.send_command(&format!("boardsize {size}"))?;
+	Ok(())
+    }
+
+    fn clear_board(&mut self) -> Result<(), GtpError> {
+	self.send_command("clear_board")?;
+	Ok(())
+    }
+
+    fn komi(&mut self, new_komi: f32) -> Result<(), GtpError> {
+	self.send_command(&format!("komi {}", new_komi.to_string()))?;
+	Ok(())
+    }
+
+    fn play(&mut self, gtp_move: GTPMove) -> Result<(), GtpError> {
+	self.send_command(&format!("play {}", gtp_move.to_string()?))?;
+	Ok(())
+    }
+
+    fn genmove(&mut self, color: Color) -> Result<GenMove, GtpError> {
+	let s = self.send_command(&format!("genmove {}", color.to_string()))?;
+	if s == "resign" {
+	    Ok(GenMove::Resign)
+	} else {
+	    Ok(GenMove::Play(Vertex::from_string(&s)?))
+	}
+    }
 }
 
-pub trait GTPEngineTournament {
-    fn fixed_handicap(&mut self, number_of_stones: u32) -> Result<Vec<Vertex>, String>;
-    fn place_free_handicap(&mut self, number_of_stones: u32) -> Result<Vec<Vertex>, String>;
-    fn set_free_handicap(&mut self, vertices: Vec<Vertex>) -> Result<(), String>;
+pub trait GTPEngineTournament: GTPEngineRaw {
+    fn fixed_handicap(&mut self, number_of_stones: u32) -> Result<Vec<Vertex>, GtpError> {
+	let s = self.send_command(&format!("fixed_handicap {number_of_stones}"))?;
+	let mut v = Vec::new();
+	for tok in s.split_whitespace() {
+	    v.push(Vertex::from_string(tok)?);
+	}
+	Ok(v)
+    }
+
+    fn place_free_handicap(&mut self, number_of_stones: u32) -> Result<Vec<Vertex>, GtpError> {
+	let s = self.send_command(&format!("place_free_handicap {number_of_stones}"))?;
+	let mut v = Vec::new();
+	for tok in s.split_whitespace() {
+	    v.push(Vertex::from_string(tok)?);
+	}
+	Ok(v)
+    }
+
+    fn set_free_handicap(&mut self, vertices: Vec<Vertex>) -> Result<(), GtpError> {
+	let mut command = String::from("set_free_handicap");
+	for v in vertices {
+	    command.push_str(" ");
+	    command.push_str(&v.to_string()?);
+	}
+	self.send_command(&command)?;
+	Ok(())
+    }
+}
+
+pub trait GTPEngineRegression: GTPEngineRaw {
+    fn loadsgf(&mut self, filename: &str, move_number: u32) -> Result<(), GtpError> {
+	self.send_command(&format!("loadsgf {filename} {move_number}"))?;
+	Ok(())
+    }
+
+    fn reg_genmove(&mut self, color: Color) -> Result<GenMove, GtpError> {
+	let s = self.send_command(&format!("reg_genmove {}", color.to_string()))?;
+	if s == "resign" {
+	    Ok(GenMove::Resign)
+	} else {
+	    Ok(GenMove::Play(Vertex::from_string(&s)?))
+	}
+    }
 }
 
-pub trait GTPEngineRegression {
-    fn loadsgf(&mut self, filename: &str, move_number: u32) -> Result<(), String>;
-    fn reg_genmove(&mut self, color: Color) -> Result<GenMove, String>;
+pub trait GTPEngineExtendedCorePlay: GTPEngineRaw {
+    fn undo(&mut self) -> Result<(), GtpError> {
+	self.send_command("undo")?;
+	Ok(())
+    }
 }
 
-pub trait GTPEngineExtendedCorePlay {
-    fn undo(&mut self) -> Result<(), String>;
+pub trait GTPEngineExtendedTournament: GTPEngineRaw {
+    fn time_settings(&mut self, main_time: u32, byo_yomi_time: u32, byo_yomi_stones: u32) -> Result<(), GtpError> {
+	self.send_command(&format!("time_settings {main_time} {byo_yomi_time} {byo_yomi_stones}"))?;
+	Ok(())
+    }
+
+    fn time_left(&mut self, color: Color, time: u32, stones: u32) -> Result<(), GtpError> {
+	self.send_command(&format!("time_left {} {} {}", color.to_string(), time, stones))?;
+	Ok(())
+    }
+
+    fn final_score(&mut self) -> Result<Score, GtpError> {
+	let s = self.send_command("final_score")?;
+	if !is_single_token(&s) {
+	    return Err(GtpError::Parse(format!("Final score '{s}' is not a single token!")));
+	}
+	Score::from_string(&s)
+    }
+
+    fn final_status_list(&mut self, status: Status) -> Result<Vec<Vertex>, GtpError> {
+	let s = self.send_command(&format!("final_status_list {}", status.to_string()))?;
+	let mut v = Vec::new();
+	for tok in s.split_whitespace() {
+	    v.push(Vertex::from_string(tok)?);
+	}
+	Ok(v)
+    }
 }
 
-pub trait GTPEngineExtendedTournament {
-    fn time_settings(&mut self, main_time: u32, byo_yomi_time: u32, byo_yomi_stones: u32) -> Result<(), String>;
-    fn time_left(&mut self, color: Color, time: u32, stones: u32) -> Result<(), String>;
-    fn final_score(&mut self) -> Result<Score, String>;
-    fn final_status_list(&mut self, status: Status) -> Result<Vec<Vertex>, String>;
+pub trait GTPEngineDebug: GTPEngineRaw {
+    fn showboard(&mut self) -> Result<String, GtpError> {
+	self.send_command("showboard")
+    }
 }
 
-pub trait GTPEngineDebug {
-    fn showboard(&mut self) -> Result<String, String>;
+
+// Analysis extension of Leela Zero, also supported by SAI.
+pub trait GTPEngineLeelaZero: GTPEngineRaw {
+    // Starts analyzing. Interval is the time between reports in
+    // centiseconds. Reports arrive as lines of the response, see
+    // parse_lz_analysis, until the next command stops the analysis.
+    fn lz_analyze(&mut self, color: Color, interval: u32) -> Result<PendingResponse, GtpError> {
+	self.send_streaming_command(&format!("lz-analyze {} {interval}", color.to_string()))
+    }
 }
 
 
 pub trait GTPEngineRaw {
     // Engine implements these methods to make raw command execution work.
     fn gen_command_id(&mut self) -> u32 { 0 }
-    fn write_to_engine(&self, s: &str) -> Result<(), String>;
-    fn read_from_engine(&mut self) -> Result<String, String>;
+    fn write_to_engine(&self, s: &str) -> Result<(), GtpError>;
+    fn read_from_engine(&mut self) -> Result<String, GtpError>;
+
+    // Engine returns a transcript to have its commands and responses recorded.
+    fn get_transcript(&mut self) -> Option<&mut Transcript> { None }
 
     // Both controller and engine can call these methods for raw command execution.
-    fn send_command(&mut self, command: &str) -> Result<String, String> {
+    fn send_command(&mut self, command: &str) -> Result<String, GtpError> {
+	let id = self.write_command(command)?;
+	self.read_response(id)
+    }
+
+    // For commands whose output streams until the next command, e.g.
+    // analysis. Returns without waiting, the lines of the response
+    // arrive as the engine writes them. Only engines that dispatch
+    // responses asynchronously through a GtpChannel can stream.
+    fn send_streaming_command(&mut self, command: &str) -> Result<PendingResponse, GtpError> {
+	Err(GtpError::Protocol(format!("Engine cannot stream '{command}'!")))
+    }
+
+    // Writes the command with a new id and returns the id.
+    fn write_command(&mut self, command: &str) -> Result<u32, GtpError> {
 	let id = self.gen_command_id();
 	let command = format!("{} {}", id, command);
 
 	self.write_to_engine(&command)?;
-	let response_str = self.read_from_engine()?;
 	println!("GTP comand  : {}", command);
-	println!("GTP response: {}", response_str);
-
-	// Check response status
-	let mut tokens = response_str.split_whitespace();
-	let first = tokens.next().ok_or("Empty GTP response")?;
-	if first == format!("={}", id) {
-	    Ok(response_str[first.len()..].trim_start().to_string())
-	} else if first == format!("?{}", id) {
-	    Err(response_str[first.len()..].trim_start().to_string())
-	} else {
-	    Err(response_str)
+	if let Some(t) = self.get_transcript() {
+	    t.record_command(&command);
+	}
+	Ok(id)
+    }
+
+    // Reads the response of the command with the id.
+    fn read_response(&mut self, id: u32) -> Result<String, GtpError> {
+	loop {
+	    let response_str = match self.read_from_engine() {
+		Ok(s) => s,
+		Err(e) => {
+		    if let Some(t) = self.get_transcript() {
+			t.record_error(&e);
+		    }
+		    return Err(e);
+		}
+	    };
+	    println!("GTP response: {}", response_str);
+	    if let Some(t) = self.get_transcript() {
+		t.record_response(&response_str);
+	    }
+
+	    // Check response status
+	    let mut tokens = response_str.split_whitespace();
+	    let first = tokens.next().ok_or(GtpError::Protocol("Empty GTP response".to_string()))?;
+	    if first == format!("={}", id) {
+		return Ok(response_str[first.len()..].trim_start().to_string());
+	    } else if first == format!("?{}", id) {
+		return Err(GtpError::EngineFailure(response_str[first.len()..].trim_start().to_string()));
+	    } else if response_id(first).is_some_and(|x| x < id) {
+		// Late response of a command that timed out earlier.
+		println!("Discarding stale GTP response.");
+	    } else {
+		return Err(GtpError::Protocol(format!("Response '{response_str}' does not match command id {id}!")));
+	    }
 	}
     }
 }
 
 
+// Id of a response whose first token is "=<id>" or "?<id>".
+fn response_id(first_token: &str) -> Option<u32> {
+    first_token.strip_prefix(['=', '?'])?.parse::<u32>().ok()
+}
+
+
 //
-// TYPES USED IN ABOVE TRAITS
+// ERRORS
 //
 
 #[derive(Debug)]
+pub enum GtpError {
+    EngineFailure(String), // Engine replied with '?' and this error message.
+    Io(String),            // Communication with the engine failed.
+    ProcessExited(String), // Engine is not running anymore.
+    Timeout(String),       // Engine didn't respond in time.
+    Protocol(String),      // Response doesn't follow the protocol or the command cannot be expressed.
+    Parse(String),         // Response or argument cannot be converted.
+}
+
+
+//
+// TYPES USED IN ABOVE TRAITS
+//
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Vertex {
     Pass,
     Coordinate(u32, u32), // Zero-indexed, origin at bottom-left corner.
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct GTPMove {
     color: Color,
     vertex: Vertex
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum GenMove {
     Resign,
     Play(Vertex),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Color {
     Black,
     White,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Status {
     Alive,
     Seki,
     Dead,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Score {
     Black(f32),
     White(f32),
     Draw,
 }
 
+// Features that need commands beyond the required ones.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Capability {
+    Undo,
+    Handicap,
+    TimeSettings,
+    FinalStatusList,
+    KataAnalyze,
+    LzAnalyze,
+    Showboard,
+}
+
+// Commands that an engine lists in list_commands.
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    commands: HashSet<String>,
+    listed: bool, // False if the engine doesn't know list_commands.
+}
+
+// A candidate move of an analysis report. Winrates and scores are
+// from the perspective of the player to move.
+#[derive(Debug, PartialEq)]
+pub struct MoveInfo {
+    pub vertex: Vertex,
+    pub visits: u32,
+    pub winrate: f32,
+    pub score_mean: Option<f32>,
+    pub score_lead: Option<f32>,
+    pub score_stdev: Option<f32>,
+    pub prior: f32,
+    pub lcb: f32,
+    pub utility: f32,
+    pub order: u32,
+    pub pv: Vec<Vertex>,
+}
+
 
 impl Vertex {
-    pub fn to_string(&self) -> Result<String, String> {
+    pub fn to_string(&self) -> Result<String, GtpError> {
 	match *self {
 	    Self::Pass => Ok("pass".to_string()),
 	    Self::Coordinate(x, y) => {
 		if x >= 25 || y >= 25 {
-		    Err(format!("Vertex coordinate ({}, {}) requires a board larger than 25x25.", x, y))
+		    Err(GtpError::Parse(format!("Vertex coordinate ({}, {}) requires a board larger than 25x25.", x, y)))
 		} else {
 		    let mut x_char = b'A' as u32 + x;
 		    if x_char >= b'I' as u32 {
 			x_char = x_char + 1;
 		    }
-		    let x_char = char::from_u32(x_char).ok_or(format!("{} cannot be converted to ASCII!", x_char))?;
+		    let x_char = char::from_u32(x_char).ok_or(GtpError::Parse(format!("{} cannot be converted to ASCII!", x_char)))?;
 		    let y_str = (y+1).to_string();
 		    Ok(format!("{}{}", x_char, y_str))
 		}
@@ -141,27 +375,27 @@ impl Vertex {
 	}
     }
 
-    pub fn from_string(s: &str) -> Result<Vertex, String> {
+    pub fn from_string(s: &str) -> Result<Vertex, GtpError> {
 	if s.len() <= 1 {
-	    Err(format!("Cannot create a Vertex from string of size {}!", s.len()))
+	    Err(GtpError::Parse(format!("Cannot create a Vertex from string of size {}!", s.len())))
 	} else if s == "pass" {
 	    Ok(Vertex::Pass)
 	} else {
 	    let mut x = s.chars().next().unwrap()
 		.to_ascii_uppercase() as i32;
 	    if x == b'I' as i32 {
-	     	return Err(format!("Illegal vertex {}!", s));
+	     	return Err(GtpError::Parse(format!("Illegal vertex {}!", s)));
 	    }
 	    if x > b'I' as i32 {
 		x -= 1;
 	    }
 	    x = x - b'A' as i32;
 	    if !(x >= 0 && x <= 25) {
-		return Err(format!("Illegal vertex {}!", s));
+		return Err(GtpError::Parse(format!("Illegal vertex {}!", s)));
 	    }
 	    	    
 	    let y = &s[1..];
-	    let y = y.parse::<u32>().map_err(|_| format!("{} cannot be parsed into u32!", y))? - 1;
+	    let y = y.parse::<u32>().map_err(|_| GtpError::Parse(format!("{} cannot be parsed into u32!", y)))? - 1;
 	    
 	    Ok(Vertex::Coordinate(x as u32, y))
 	}
@@ -174,7 +408,7 @@ impl GTPMove {
 	Self { color: color, vertex: vertex }
     }
 
-    pub fn to_string(&self) -> Result<String, String> {
+    pub fn to_string(&self) -> Result<String, GtpError> {
 	Ok(format!("{} {}", self.color.to_string(), self.vertex.to_string()?))
     }
 }
@@ -202,26 +436,26 @@ impl Status {
 
 
 impl Score {
-    pub fn from_string(s: &str) -> Result<Self, String> {
+    pub fn from_string(s: &str) -> Result<Self, GtpError> {
 	if !is_single_token(s) {
-	    return Err(format!("Score '{s}' is not single token!"));
+	    return Err(GtpError::Parse(format!("Score '{s}' is not single token!")));
 	}
 	if s == "0" {
 	    Ok(Self::Draw)
 	} else {
 	    let v: Vec<_> = s.split("+").collect();
 	    if v.len() != 2 {
-		Err(format!("Illegal score format: {s}"))
+		Err(GtpError::Parse(format!("Illegal score format: {s}")))
 	    } else {
 		let color = v[0];
 		let score = v[1];
-		let score = score.parse::<f32>().map_err(|_| format!("Cannot parse score {score}!"))?;
+		let score = score.parse::<f32>().map_err(|_| GtpError::Parse(format!("Cannot parse score {score}!")))?;
 		if color == "B" {
 		    Ok(Self::Black(score))
 		} else if color == "W" {
 		    Ok(Self::White(score))
 		} else {
-		    Err(format!("Illegal score format: {s}"))
+		    Err(GtpError::Parse(format!("Illegal score format: {s}")))
 		}
 	    }
 	}
@@ -229,10 +463,205 @@ impl Score {
 }
 
 
+impl Capability {
+    // Commands that all have to be known for the capability.
+    fn commands(&self) -> &'static [&'static str] {
+	match *self {
+	    Self::Undo => &["undo"],
+	    Self::Handicap => &["fixed_handicap", "place_free_handicap", "set_free_handicap"],
+	    Self::TimeSettings => &["time_settings", "time_left"],
+	    Self::FinalStatusList => &["final_status_list"],
+	    Self::KataAnalyze => &["kata-analyze"],
+	    Self::LzAnalyze => &["lz-analyze"],
+	    Self::Showboard => &["showboard"],
+	}
+    }
+}
+
+
+impl Capabilities {
+    pub fn from_commands(commands: Vec<String>) -> Self {
+	Self { commands: commands.into_iter().collect(), listed: true }
+    }
+
+    // Asks the engine for its commands. An engine that doesn't know
+    // list_commands is assumed to know only the required commands,
+    // unless asked with check.
+    pub fn query<E: GTPEngineMinimal>(engine: &mut E) -> Result<Self, GtpError> {
+	match engine.list_commands() {
+	    Ok(commands) => Ok(Self::from_commands(commands)),
+	    Err(GtpError::EngineFailure(s)) => {
+		println!("Engine doesn't list its commands: {s}");
+		Ok(Self::default())
+	    },
+	    Err(e) => Err(e),
+	}
+    }
+
+    pub fn has_command(&self, command_name: &str) -> bool {
+	self.commands.contains(command_name)
+    }
+
+    pub fn has(&self, capability: Capability) -> bool {
+	capability.commands().iter().all(|x| self.has_command(x))
+    }
+
+    pub fn is_listed(&self) -> bool {
+	self.listed
+    }
+
+    // Like has, but asks an engine that doesn't list its commands with
+    // known_command.
+    pub fn check<E: GTPEngineMinimal + ?Sized>(&self, engine: &mut E, capability: Capability) -> Result<bool, GtpError> {
+	if self.listed {
+	    return Ok(self.has(capability));
+	}
+	for command_name in capability.commands() {
+	    if !engine.known_command(command_name)? {
+		return Ok(false);
+	    }
+	}
+	Ok(true)
+    }
+}
+
+
+impl MoveInfo {
+    fn new() -> Self {
+	Self {
+	    vertex: Vertex::Pass,
+	    visits: 0,
+	    winrate: 0.0,
+	    score_mean: None,
+	    score_lead: None,
+	    score_stdev: None,
+	    prior: 0.0,
+	    lcb: 0.0,
+	    utility: 0.0,
+	    order: 0,
+	    pv: Vec::new(),
+	}
+    }
+
+    // Unknown keys are ignored, engines keep adding more.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), GtpError> {
+	match key {
+	    "move" => { self.vertex = Vertex::from_string(value)?; },
+	    "visits" => { self.visits = parse_number(key, value)?; },
+	    "winrate" => { self.winrate = parse_number(key, value)?; },
+	    "scoreMean" => { self.score_mean = Some(parse_number(key, value)?); },
+	    "scoreLead" => { self.score_lead = Some(parse_number(key, value)?); },
+	    "scoreStdev" => { self.score_stdev = Some(parse_number(key, value)?); },
+	    "prior" => { self.prior = parse_number(key, value)?; },
+	    "lcb" => { self.lcb = parse_number(key, value)?; },
+	    "utility" => { self.utility = parse_number(key, value)?; },
+	    "order" => { self.order = parse_number(key, value)?; },
+	    _ => (),
+	}
+	Ok(())
+    }
+}
+
+
+impl GtpError {
+    // True if the engine is still usable after this error, e.g. it
+    // rejected an illegal move. After a timeout the late response is
+    // discarded by send_command.
+    pub fn is_recoverable(&self) -> bool {
+	match *self {
+	    Self::EngineFailure(_) | Self::Parse(_) | Self::Timeout(_) => true,
+	    Self::Io(_) | Self::ProcessExited(_) | Self::Protocol(_) => false,
+	}
+    }
+}
+
+
+impl fmt::Display for GtpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    Self::EngineFailure(s) => write!(f, "Engine failure: {s}"),
+	    Self::Io(s) => write!(f, "Engine I/O error: {s}"),
+	    Self::ProcessExited(s) => write!(f, "Engine process exited: {s}"),
+	    Self::Timeout(s) => write!(f, "Engine timeout: {s}"),
+	    Self::Protocol(s) => write!(f, "GTP protocol error: {s}"),
+	    Self::Parse(s) => write!(f, "GTP parse error: {s}"),
+	}
+    }
+}
+
+
+impl Error for GtpError {}
+
+
+// Lets functions that report errors as String use the ? operator on
+// GTP calls.
+impl From<GtpError> for String {
+    fn from(e: GtpError) -> Self {
+	e.to_string()
+    }
+}
+
+
 //
 // ADDITIONAL UTILITY CODE
 //
 
+// Parses one report line of the analysis commands, which is a list
+// of "info <key> <value> ... pv <vertices>" entries, optionally
+// followed by "ownership <values>".
+pub fn parse_analysis_info(s: &str) -> Result<(Vec<MoveInfo>, Option<Vec<f32>>), GtpError> {
+    let mut move_infos = Vec::new();
+    let mut ownership = None;
+
+    let mut tokens = s.split_whitespace().peekable();
+    while let Some(tok) = tokens.next() {
+	match tok {
+	    "info" => { move_infos.push(MoveInfo::new()); },
+	    "ownership" => {
+		let mut v = Vec::new();
+		while let Some(x) = tokens.peek().and_then(|x| x.parse::<f32>().ok()) {
+		    v.push(x);
+		    tokens.next();
+		}
+		ownership = Some(v);
+	    },
+	    key => {
+		let info = move_infos.last_mut()
+		    .ok_or(GtpError::Parse(format!("Analysis doesn't start with info: {s}")))?;
+		if key == "pv" {
+		    while let Some(v) = tokens.peek().and_then(|x| Vertex::from_string(x).ok()) {
+			info.pv.push(v);
+			tokens.next();
+		    }
+		    continue;
+		}
+		let value = tokens.next().ok_or(GtpError::Parse(format!("Analysis key {key} has no value!")))?;
+		info.set(key, value)?;
+	    },
+	}
+    }
+    Ok((move_infos, ownership))
+}
+
+
+// Parses one report line of lz-analyze. Leela Zero reports winrates
+// and priors in 1/10000.
+pub fn parse_lz_analysis(s: &str) -> Result<Vec<MoveInfo>, GtpError> {
+    let (mut move_infos, _) = parse_analysis_info(s)?;
+    for info in move_infos.iter_mut() {
+	info.winrate /= 10000.0;
+	info.prior /= 10000.0;
+	info.lcb /= 10000.0;
+    }
+    Ok(move_infos)
+}
+
+
+pub fn parse_number<T: str::FromStr>(key: &str, value: &str) -> Result<T, GtpError> {
+    value.parse::<T>().map_err(|_| GtpError::Parse(format!("Cannot parse {key} value {value}!")))
+}
+
+
 pub fn is_single_token(s: &str) -> bool {
     if s.len() == 0 {
 	return false;
@@ -242,3 +671,119 @@ pub fn is_single_token(s: &str) -> bool {
     }
     true
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_engine::MockEngine;
+    use std::time::Duration;
+
+    #[test]
+    fn protocol() {
+	let mut engine = MockEngine::new();
+
+	assert_eq!(engine.protocol_version().unwrap(), 2);
+	assert_eq!(engine.name().unwrap(), "Mock");
+	assert_eq!(engine.version().unwrap(), "1.0");
+	assert!(engine.known_command("known_command").unwrap());
+	assert!(!engine.known_command("asdf").unwrap());
+	assert!(engine.known_command("two tokens").is_err());
+	assert!(engine.list_commands().unwrap().contains(&"genmove".to_string()));
+
+	engine.boardsize(19).unwrap();
+	engine.clear_board().unwrap();
+	engine.komi(2.5).unwrap();
+	assert_eq!(engine.fixed_handicap(5).unwrap().len(), 5);
+	assert_eq!(engine.place_free_handicap(5).unwrap()[0], Vertex::Coordinate(3, 3));
+	engine.set_free_handicap(vec![Vertex::Coordinate(0, 0), Vertex::Coordinate(18, 18)]).unwrap();
+
+	engine.play(GTPMove::new(Color::Black, Vertex::Coordinate(0, 1))).unwrap();
+	engine.play(GTPMove::new(Color::White, Vertex::Pass)).unwrap();
+	assert_eq!(engine.genmove(Color::Black).unwrap(), GenMove::Play(Vertex::Coordinate(3, 3)));
+	engine.undo().unwrap();
+
+	engine.time_settings(1000, 1000, 1000).unwrap();
+	engine.time_left(Color::Black, 1000, 1000).unwrap();
+	assert_eq!(engine.final_score().unwrap(), Score::White(2.5));
+	assert_eq!(engine.final_status_list(Status::Dead).unwrap(), vec![Vertex::Coordinate(3, 3), Vertex::Coordinate(15, 15)]);
+
+	engine.loadsgf("game.sgf", 10).unwrap();
+	assert_eq!(engine.reg_genmove(Color::White).unwrap(), GenMove::Play(Vertex::Coordinate(15, 15)));
+	assert!(engine.showboard().unwrap().contains("X"));
+
+	engine.set_response("genmove", "resign");
+	assert_eq!(engine.genmove(Color::White).unwrap(), GenMove::Resign);
+
+	let commands = engine.get_commands();
+	assert!(commands.contains(&"set_free_handicap A1 T19".to_string()));
+	assert!(commands.contains(&"play B A2".to_string()));
+	assert!(commands.contains(&"play W pass".to_string()));
+	assert!(commands.contains(&"loadsgf game.sgf 10".to_string()));
+
+	engine.quit().unwrap();
+    }
+
+    #[test]
+    fn errors() {
+	let mut engine = MockEngine::new();
+	engine.set_failure("play", "illegal move");
+	engine.set_response("genmove", "I5");
+	engine.set_delay("name", Duration::from_millis(10));
+	engine.set_crash_after(4);
+
+	let e = engine.play(GTPMove::new(Color::Black, Vertex::Coordinate(0, 0))).unwrap_err();
+	assert!(matches!(&e, GtpError::EngineFailure(s) if s == "illegal move"));
+	assert!(e.is_recoverable());
+	assert!(matches!(engine.genmove(Color::Black), Err(GtpError::Parse(_))));
+	assert!(matches!(engine.undo(), Ok(())));
+	assert_eq!(engine.name().unwrap(), "Mock");
+
+	let e = engine.version().unwrap_err();
+	assert!(matches!(e, GtpError::ProcessExited(_)));
+	assert!(!e.is_recoverable());
+    }
+
+    #[test]
+    fn lz_analyze() {
+	let s = "info move D4 visits 10 winrate 4800 prior 2500 lcb 4700 order 0 pv D4 Q16 info move Q16 visits 2 winrate 4500 areas 5000 prior 1000 lcb 4000 order 1 pv Q16";
+	let infos = parse_lz_analysis(s).unwrap();
+	assert_eq!(infos.len(), 2);
+	assert_eq!(infos[0].winrate, 0.48);
+	assert_eq!(infos[0].pv, vec![Vertex::Coordinate(3, 3), Vertex::Coordinate(15, 15)]);
+	assert_eq!(infos[1].order, 1);
+	assert_eq!(infos[1].prior, 0.1);
+
+	// Streaming needs a channel.
+	let mut engine = MockEngine::new();
+	assert!(matches!(engine.lz_analyze(Color::White, 10), Err(GtpError::Protocol(_))));
+    }
+
+    #[test]
+    fn capabilities() {
+	let mut engine = MockEngine::new();
+	let capabilities = Capabilities::query(&mut engine).unwrap();
+	assert!(capabilities.has(Capability::Undo));
+	assert!(capabilities.has(Capability::Handicap));
+	assert!(!capabilities.has(Capability::KataAnalyze));
+	assert!(capabilities.has_command("list_commands"));
+
+	engine.set_failure("list_commands", "unknown command");
+	let capabilities = Capabilities::query(&mut engine).unwrap();
+	assert!(!capabilities.has(Capability::Undo));
+	assert!(!capabilities.is_listed());
+	assert!(capabilities.check(&mut engine, Capability::Undo).unwrap());
+	assert!(!capabilities.check(&mut engine, Capability::LzAnalyze).unwrap());
+	engine.set_response("lz-analyze", "");
+	assert!(capabilities.check(&mut engine, Capability::LzAnalyze).unwrap());
+    }
+
+    #[test]
+    fn vertex() {
+	assert_eq!(Vertex::from_string("J10").unwrap(), Vertex::Coordinate(8, 9));
+	assert_eq!(Vertex::Coordinate(8, 9).to_string().unwrap(), "J10");
+	assert_eq!(Vertex::from_string("pass").unwrap(), Vertex::Pass);
+	assert!(matches!(Vertex::from_string("I5"), Err(GtpError::Parse(_))));
+	assert!(matches!(Vertex::Coordinate(25, 0).to_string(), Err(GtpError::Parse(_))));
+    }
+}
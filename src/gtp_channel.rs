@@ -0,0 +1,395 @@
+// Asynchronous GTP command channel. Commands are written right away
+// and responses are matched to commands by id, so several commands
+// can be in flight and several parts of the program can share one
+// engine. Lines of a response are delivered as they arrive, which
+// makes streaming commands like kata-analyze usable. Such a command
+// is interrupted by sending any new command.
+
+use crate::gtp::*;
+use crate::gtp_katago::KataGoGtpExtensions;
+use crate::child_process_engine::DEFAULT_COMMAND_TIMEOUT;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError, channel};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+
+// Handle to the channel. Clones share the engine and the command ids,
+// each clone has its own command timeout.
+pub struct GtpChannel {
+    shared: Arc<Shared>,
+    command_timeout: Option<Duration>, // None waits forever.
+    raw_tx: Subscriber, // Responses of the commands written through GTPEngineRaw,
+    raw_rx: Receiver<(u32, ResponseEvent)>, // in the order they arrive.
+}
+
+struct Shared {
+    next_id: AtomicU32,
+    writer: Mutex<Box<dyn Write + Send>>,
+    subscribers: Subscribers,
+}
+
+// Senders of the commands waiting for a response, keyed by command
+// id. None after the engine closed its output.
+type Subscribers = Arc<Mutex<Option<BTreeMap<u32, Subscriber>>>>;
+
+// Events are sent with the id of their command.
+type Subscriber = Sender<(u32, ResponseEvent)>;
+
+// Response of a command sent through the channel.
+pub struct PendingResponse {
+    rx: Receiver<(u32, ResponseEvent)>,
+}
+
+#[derive(Debug)]
+pub enum ResponseEvent {
+    Line(String), // A line of the response as it arrives, without status and id.
+    Done(Result<String, GtpError>), // Complete response, like send_command returns it.
+}
+
+
+impl GtpChannel {
+    // Runs the engine over the streams, e.g. the stdout and stdin of
+    // a child process or the halves of a socket.
+    pub fn new<R, W>(reader: R, writer: W) -> Self
+    where
+	R: Read + Send + 'static,
+	W: Write + Send + 'static,
+    {
+	let subscribers = Arc::new(Mutex::new(Some(BTreeMap::new())));
+	let s = subscribers.clone();
+	thread::spawn(move || dispatch_responses(reader, s));
+
+	let (raw_tx, raw_rx) = channel();
+	Self {
+	    shared: Arc::new(Shared {
+		next_id: AtomicU32::new(1),
+		writer: Mutex::new(Box::new(writer)),
+		subscribers: subscribers,
+	    }),
+	    command_timeout: Some(DEFAULT_COMMAND_TIMEOUT),
+	    raw_tx: raw_tx,
+	    raw_rx: raw_rx,
+	}
+    }
+
+    pub fn get_command_timeout(&self) -> Option<Duration> {
+	self.command_timeout
+    }
+
+    pub fn set_command_timeout(&mut self, timeout: Option<Duration>) {
+	self.command_timeout = timeout;
+    }
+
+    // Writes the command and returns without waiting for the response.
+    pub fn send(&self, command: &str) -> Result<PendingResponse, GtpError> {
+	let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+	let (tx, rx) = channel();
+	self.send_with_id(id, command, tx)?;
+	Ok(PendingResponse { rx: rx })
+    }
+
+    // Stops a streaming command by sending a command without side
+    // effects. The response of the streaming command then completes.
+    pub fn interrupt(&self) -> Result<(), GtpError> {
+	self.send("protocol_version")?;
+	Ok(())
+    }
+
+    fn send_with_id(&self, id: u32, command: &str, tx: Subscriber) -> Result<(), GtpError> {
+	// Subscribing before writing so that the response cannot be missed.
+	match self.shared.subscribers.lock().unwrap().as_mut() {
+	    Some(subscribers) => { subscribers.insert(id, tx); },
+	    None => { return Err(disconnected_error()); },
+	}
+
+	let command = format!("{id} {}", command.trim());
+	println!("GTP comand  : {}", command);
+	let mut writer = self.shared.writer.lock().unwrap();
+	let r = writer.write_all(format!("{command}\n").as_bytes()).and_then(|_| writer.flush());
+	if let Err(e) = r {
+	    if let Some(subscribers) = self.shared.subscribers.lock().unwrap().as_mut() {
+		subscribers.remove(&id);
+	    }
+	    return Err(GtpError::ProcessExited(format!("Cannot write to engine: {e}")));
+	}
+	Ok(())
+    }
+}
+
+
+impl Clone for GtpChannel {
+    fn clone(&self) -> Self {
+	let (raw_tx, raw_rx) = channel();
+	Self {
+	    shared: self.shared.clone(),
+	    command_timeout: self.command_timeout,
+	    raw_tx: raw_tx,
+	    raw_rx: raw_rx,
+	}
+    }
+}
+
+
+impl PendingResponse {
+    // Next event if there is one, without blocking. Meant for polling
+    // from the UI.
+    pub fn try_next(&self) -> Option<ResponseEvent> {
+	match self.rx.try_recv() {
+	    Ok((_, event)) => Some(event),
+	    Err(TryRecvError::Empty) => None,
+	    Err(TryRecvError::Disconnected) => Some(ResponseEvent::Done(Err(disconnected_error()))),
+	}
+    }
+
+    // Next event, waiting at most timeout. None waits forever. Only
+    // tests wait, the program polls.
+    #[cfg(test)]
+    pub fn next(&self, timeout: Option<Duration>) -> Result<ResponseEvent, GtpError> {
+	let r = match timeout {
+	    Some(timeout) => self.rx.recv_timeout(timeout),
+	    None => self.rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+	};
+	match r {
+	    Ok((_, event)) => Ok(event),
+	    Err(RecvTimeoutError::Timeout) => Err(GtpError::Timeout(format!("No response within {:?}.", timeout.unwrap_or_default()))),
+	    Err(RecvTimeoutError::Disconnected) => Err(disconnected_error()),
+	}
+    }
+
+    // Waits for the complete response. Timeout applies to each line.
+    #[cfg(test)]
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<String, GtpError> {
+	loop {
+	    if let ResponseEvent::Done(r) = self.next(timeout)? {
+		return r;
+	    }
+	}
+    }
+}
+
+
+// Lets the GTP traits run on the channel. Commands block until their
+// response arrives, but other handles can keep sending meanwhile.
+// Responses are read in the order they arrive, so the late response
+// of a command that timed out is read and discarded by read_response.
+impl GTPEngineRaw for GtpChannel {
+    fn gen_command_id(&mut self) -> u32 {
+	self.shared.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn write_to_engine(&self, s: &str) -> Result<(), GtpError> {
+	let (id, command) = s.trim().split_once(' ').unwrap_or((s.trim(), ""));
+	let id = id.parse::<u32>().map_err(|_| GtpError::Protocol(format!("Command '{s}' has no id!")))?;
+	self.send_with_id(id, command, self.raw_tx.clone())
+    }
+
+    fn read_from_engine(&mut self) -> Result<String, GtpError> {
+	loop {
+	    let r = match self.command_timeout {
+		Some(timeout) => self.raw_rx.recv_timeout(timeout),
+		None => self.raw_rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+	    };
+	    match r {
+		Ok((id, ResponseEvent::Done(Ok(s)))) => { return Ok(format!("={id} {s}")); },
+		Ok((id, ResponseEvent::Done(Err(GtpError::EngineFailure(s))))) => { return Ok(format!("?{id} {s}")); },
+		Ok((_, ResponseEvent::Done(Err(e)))) => { return Err(e); },
+		Ok((_, ResponseEvent::Line(_))) => (),
+		Err(RecvTimeoutError::Timeout) => { return Err(GtpError::Timeout(format!("No response within {:?}.", self.command_timeout.unwrap_or_default()))); },
+		Err(RecvTimeoutError::Disconnected) => { return Err(disconnected_error()); },
+	    }
+	}
+    }
+
+    fn send_streaming_command(&mut self, command: &str) -> Result<PendingResponse, GtpError> {
+	self.send(command)
+    }
+}
+
+
+impl GTPEngineMinimal for GtpChannel {}
+
+
+impl GTPEngineTournament for GtpChannel {}
+
+
+impl GTPEngineRegression for GtpChannel {}
+
+
+impl GTPEngineExtendedCorePlay for GtpChannel {}
+
+
+impl GTPEngineExtendedTournament for GtpChannel {}
+
+
+impl GTPEngineDebug for GtpChannel {}
+
+
+impl GTPEngineLeelaZero for GtpChannel {}
+
+
+impl KataGoGtpExtensions for GtpChannel {}
+
+
+// Reads the engine output and sends each line to the subscriber of
+// the response it belongs to. Ends when the engine closes the
+// stream, failing the commands that are still waiting.
+fn dispatch_responses<R: Read>(stream: R, subscribers: Subscribers) {
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    let mut current: Option<(u32, Option<Subscriber>, bool, Vec<String>)> = None; // Id, subscriber, success, lines.
+
+    loop {
+	line.clear();
+	match reader.read_until(b'\n', &mut line) {
+	    Ok(0) | Err(_) => { break; },
+	    Ok(_) => (),
+	}
+	let s = String::from_utf8_lossy(&line);
+	let s = s.trim_end_matches(['\r', '\n']);
+
+	match current.take() {
+	    None if s.is_empty() => (),
+	    None => {
+		// First line of a response: status, optional id, text.
+		let success = s.starts_with('=');
+		let rest = &s[1.min(s.len())..];
+		let text = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+		let mut subscribers = subscribers.lock().unwrap();
+		let subscribers = subscribers.get_or_insert_with(BTreeMap::new);
+		let (id, tx) = match rest[..rest.len() - text.len()].parse::<u32>() {
+		    Ok(id) => (id, subscribers.remove(&id)),
+		    // Without an id, the response is for the oldest command.
+		    Err(_) => subscribers.pop_first().map(|(id, tx)| (id, Some(tx))).unwrap_or((0, None)),
+		};
+		let text = text.trim_start();
+		if let (Some(tx), false) = (&tx, text.is_empty()) {
+		    let _ = tx.send((id, ResponseEvent::Line(text.to_string())));
+		}
+		current = Some((id, tx, success, vec![text.to_string()]));
+	    },
+	    Some((id, tx, success, lines)) if s.is_empty() => {
+		let text = lines.join("\n").trim().to_string();
+		if let Some(tx) = tx {
+		    let r = if success { Ok(text) } else { Err(GtpError::EngineFailure(text)) };
+		    // The receiver may be gone, e.g. after interrupt.
+		    let _ = tx.send((id, ResponseEvent::Done(r)));
+		}
+	    },
+	    Some((id, tx, success, mut lines)) => {
+		if let Some(tx) = &tx {
+		    let _ = tx.send((id, ResponseEvent::Line(s.to_string())));
+		}
+		lines.push(s.to_string());
+		current = Some((id, tx, success, lines));
+	    },
+	}
+    }
+
+    // Failing the waiting commands explicitly, since the handles keep
+    // the senders of their own commands alive.
+    let mut waiting: Vec<_> = current.into_iter().filter_map(|(id, tx, _, _)| Some((id, tx?))).collect();
+    waiting.extend(subscribers.lock().unwrap().take().unwrap_or_default());
+    for (id, tx) in waiting {
+	let _ = tx.send((id, ResponseEvent::Done(Err(disconnected_error()))));
+    }
+}
+
+
+fn disconnected_error() -> GtpError {
+    GtpError::ProcessExited("Engine closed the connection.".to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtp_katago::KataAnalysis;
+    use std::net::{TcpListener, TcpStream};
+
+    // Engine that streams two info lines for kata-analyze until the
+    // next command arrives, answers name and quit, and answers a pair
+    // of "swap" commands in reverse order.
+    fn serve(stream: TcpStream) {
+	let mut writer = stream.try_clone().unwrap();
+	let mut lines = BufReader::new(stream).lines();
+	let mut swapped = None;
+	let mut streaming = false;
+	while let Some(Ok(line)) = lines.next() {
+	    let tokens: Vec<_> = line.split_whitespace().collect();
+	    let (id, command) = (tokens[0], tokens[1]);
+	    if streaming {
+		// Ends the stream.
+		writer.write_all(b"\n").unwrap();
+		streaming = false;
+	    }
+	    let response = match command {
+		"kata-analyze" => {
+		    writer.write_all(format!("={id}\ninfo move D4 visits 1 winrate 0.5\ninfo move D4 visits 2 winrate 0.6\n").as_bytes()).unwrap();
+		    streaming = true;
+		    continue;
+		},
+		"name" => format!("={id} Remote\n\n"),
+		"quit" => {
+		    writer.write_all(format!("={id}\n\n").as_bytes()).unwrap();
+		    break;
+		},
+		"swap" => match swapped.take() {
+		    None => { swapped = Some(id.to_string()); continue; },
+		    Some(first) => format!("={id} second\n\n={first} first\n\n"),
+		},
+		_ => format!("?{id} unknown command\n\n"),
+	    };
+	    writer.write_all(response.as_bytes()).unwrap();
+	}
+    }
+
+    fn connect() -> (GtpChannel, thread::JoinHandle<()>) {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let address = listener.local_addr().unwrap();
+	let server = thread::spawn(move || serve(listener.accept().unwrap().0));
+	let stream = TcpStream::connect(address).unwrap();
+	(GtpChannel::new(stream.try_clone().unwrap(), stream), server)
+    }
+
+    #[test]
+    fn streaming() {
+	let timeout = Some(Duration::from_secs(5));
+	let (mut channel, server) = connect();
+
+	let analysis = channel.kata_analyze(Color::Black, 10, false).unwrap();
+	for visits in [1, 2] {
+	    match analysis.next(timeout).unwrap() {
+		ResponseEvent::Line(s) => { assert_eq!(KataAnalysis::from_string(&s).unwrap().move_infos[0].visits, visits); },
+		e => { panic!("Expected a line, got {e:?}"); },
+	    }
+	}
+
+	// Another handle interrupts the stream with its own command.
+	let mut other = channel.clone();
+	assert_eq!(other.name().unwrap(), "Remote");
+	let s = analysis.wait(timeout).unwrap();
+	assert_eq!(s.lines().count(), 2);
+	assert!(matches!(other.undo(), Err(GtpError::EngineFailure(_))));
+
+	other.quit().unwrap();
+	server.join().unwrap();
+	let r = channel.send("name").and_then(|x| x.wait(timeout));
+	assert!(matches!(r, Err(GtpError::ProcessExited(_))));
+	assert!(matches!(channel.name(), Err(GtpError::ProcessExited(_))));
+    }
+
+    #[test]
+    fn pipelined() {
+	let timeout = Some(Duration::from_secs(5));
+	let (channel, _server) = connect();
+
+	let first = channel.send("swap").unwrap();
+	let second = channel.send("swap").unwrap();
+	assert_eq!(first.wait(timeout).unwrap(), "first");
+	assert_eq!(second.wait(timeout).unwrap(), "second");
+    }
+}
@@ -0,0 +1,62 @@
+// KataGo specific GTP extensions.
+// https://github.com/lightvector/KataGo/blob/master/docs/GTP_Extensions.md
+
+use crate::gtp::*;
+use crate::gtp_channel::PendingResponse;
+
+
+pub trait KataGoGtpExtensions: GTPEngineRaw {
+    // Starts analyzing. Interval is the time between reports in
+    // centiseconds. Reports arrive as lines of the response, see
+    // KataAnalysis::from_string, until the next command stops the
+    // analysis.
+    fn kata_analyze(&mut self, color: Color, interval: u32, ownership: bool) -> Result<PendingResponse, GtpError> {
+	self.send_streaming_command(&format!("kata-analyze {} {interval}{}", color.to_string(), ownership_arg(ownership)))
+    }
+}
+
+
+//
+// TYPES USED IN ABOVE TRAIT
+//
+
+#[derive(Debug, PartialEq)]
+pub struct KataAnalysis {
+    pub move_infos: Vec<MoveInfo>,
+    pub ownership: Option<Vec<f32>>, // Row by row from the top-left corner, positive for white.
+}
+
+
+impl KataAnalysis {
+    // Parses one report line of kata-analyze.
+    pub fn from_string(s: &str) -> Result<Self, GtpError> {
+	let (move_infos, ownership) = parse_analysis_info(s)?;
+	Ok(Self { move_infos: move_infos, ownership: ownership })
+    }
+}
+
+
+fn ownership_arg(ownership: bool) -> &'static str {
+    if ownership { " ownership true" } else { "" }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn analysis() {
+	let s = "info move D4 visits 120 edgeVisits 120 utility 0.05 winrate 0.52 scoreMean 0.8 scoreStdev 12.1 scoreLead 0.7 scoreSelfplay 0.9 prior 0.2 lcb 0.5 utilityLcb 0.01 order 0 pv D4 Q16 C3 info move pass visits 3 utility -0.2 winrate 0.4 prior 0.01 lcb 0.3 order 1 pv pass ownership 0.5 -0.25 0.0 1.0";
+	let a = KataAnalysis::from_string(s).unwrap();
+	assert_eq!(a.move_infos.len(), 2);
+	let m = &a.move_infos[0];
+	assert_eq!(m.vertex, Vertex::Coordinate(3, 3));
+	assert_eq!(m.visits, 120);
+	assert_eq!(m.score_lead, Some(0.7));
+	assert_eq!(m.pv, vec![Vertex::Coordinate(3, 3), Vertex::Coordinate(15, 15), Vertex::Coordinate(2, 2)]);
+	assert_eq!(a.move_infos[1].vertex, Vertex::Pass);
+	assert_eq!(a.move_infos[1].score_mean, None);
+	assert_eq!(a.ownership, Some(vec![0.5, -0.25, 0.0, 1.0]));
+    }
+}
@@ -0,0 +1,242 @@
+// Runner for regression test files in the format of gogui-regress.
+//
+// A test file is a list of GTP commands. A command with a numeric id
+// can be followed by the expected response:
+//
+//   loadsgf games/ladder.sgf 12
+//   10 reg_genmove black
+//   #? [C3|D4]
+//
+// The expected response is a regular expression matching the whole
+// response. A leading '!' negates it and a trailing '*' marks a known
+// failure. Other lines starting with '#' are comments. SGF files of
+// loadsgf are relative to the test file.
+
+use crate::gtp::*;
+use crate::child_process_engine::ChildProcessEngine;
+use regex::Regex;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+
+struct TestEntry {
+    line: usize,
+    id: Option<u32>,
+    command: String,
+    expected: Option<Expected>,
+}
+
+struct Expected {
+    text: String, // As written in the file, for the report.
+    regex: Regex,
+    negated: bool,
+    known_failure: bool,
+}
+
+pub struct TestResult {
+    pub line: usize,
+    pub id: u32,
+    pub command: String,
+    pub expected: String,
+    pub response: Result<String, String>,
+    pub passed: bool,
+    pub known_failure: bool,
+}
+
+pub struct RegressionReport {
+    pub file: PathBuf,
+    pub results: Vec<TestResult>,
+}
+
+
+// Runs the test file on the engine. Commands without an expected
+// response only have to succeed.
+pub fn run_test_file<E: GTPEngineRegression>(engine: &mut E, path: &Path) -> Result<RegressionReport, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Cannot read test file {path:?}: {e}"))?;
+    let entries = parse_test_file(&content)?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    let mut results = Vec::new();
+    for entry in entries {
+	let response = run_command(engine, dir, &entry.command).map_err(|e| e.to_string());
+	match (&entry.expected, entry.id) {
+	    (Some(expected), Some(id)) => {
+		let passed = match &response {
+		    Ok(s) => expected.regex.is_match(s.trim()) != expected.negated,
+		    Err(_) => false,
+		};
+		results.push(TestResult {
+		    line: entry.line,
+		    id: id,
+		    command: entry.command,
+		    expected: expected.text.clone(),
+		    response: response,
+		    passed: passed,
+		    known_failure: expected.known_failure,
+		});
+	    },
+	    _ => {
+		if let Err(s) = response {
+		    println!("Command '{}' on line {} failed: {s}", entry.command, entry.line);
+		}
+	    },
+	}
+    }
+    Ok(RegressionReport { file: path.to_path_buf(), results: results })
+}
+
+
+// Runs "regress <engine command> <test files>..." from the command
+// line. Each file gets a fresh engine. Fails if a test fails
+// unexpectedly.
+pub fn run_from_args(args: &[String]) -> Result<(), String> {
+    let (command, files) = match args {
+	[command, files @ ..] if !files.is_empty() => (command, files),
+	_ => { return Err("Usage: go-game regress \"<engine command>\" <test file>...".to_string()); },
+    };
+
+    let mut unexpected = 0;
+    for file in files {
+	let mut engine = ChildProcessEngine::new(command)?;
+	let report = run_test_file(&mut engine, Path::new(file))?;
+	print!("{report}");
+	unexpected += report.count_unexpected_failures();
+	if let Err(e) = engine.quit() {
+	    println!("Engine didn't quit cleanly: {e}");
+	}
+    }
+
+    if unexpected > 0 {
+	Err(format!("{unexpected} unexpected failures."))
+    } else {
+	Ok(())
+    }
+}
+
+
+impl RegressionReport {
+    pub fn count_passed(&self) -> usize {
+	self.results.iter().filter(|x| x.passed).count()
+    }
+
+    pub fn count_unexpected_failures(&self) -> usize {
+	self.results.iter().filter(|x| !x.passed && !x.known_failure).count()
+    }
+
+    // Tests that were marked as known failures but passed.
+    pub fn count_unexpected_passes(&self) -> usize {
+	self.results.iter().filter(|x| x.passed && x.known_failure).count()
+    }
+}
+
+
+impl fmt::Display for RegressionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	writeln!(f, "Regression results for {}:", self.file.display())?;
+	for r in &self.results {
+	    let status = match (r.passed, r.known_failure) {
+		(true, false) => "passed",
+		(true, true) => "PASSED (unexpected)",
+		(false, true) => "failed (known)",
+		(false, false) => "FAILED",
+	    };
+	    let response = match &r.response {
+		Ok(s) => s.clone(),
+		Err(s) => format!("error: {s}"),
+	    };
+	    writeln!(f, "  line {}: {} {}: {status}, expected {}, got '{}'", r.line, r.id, r.command, r.expected, response)?;
+	}
+	writeln!(f, "  {} tests, {} passed, {} unexpected failures, {} unexpected passes",
+		 self.results.len(), self.count_passed(), self.count_unexpected_failures(), self.count_unexpected_passes())
+    }
+}
+
+
+fn run_command<E: GTPEngineRegression>(engine: &mut E, dir: &Path, command: &str) -> Result<String, GtpError> {
+    let tokens: Vec<_> = command.split_whitespace().collect();
+    match tokens[..] {
+	["loadsgf", file, move_number] if move_number.parse::<u32>().is_ok() => {
+	    let file = dir.join(file);
+	    let file = file.to_str().ok_or(GtpError::Protocol(format!("Path {file:?} is not UTF-8!")))?;
+	    engine.loadsgf(file, move_number.parse().unwrap())?;
+	    Ok(String::new())
+	},
+	["loadsgf", file] => {
+	    engine.send_command(&format!("loadsgf {}", dir.join(file).display()))
+	},
+	_ => engine.send_command(command),
+    }
+}
+
+
+fn parse_test_file(content: &str) -> Result<Vec<TestEntry>, String> {
+    let mut entries: Vec<TestEntry> = Vec::new();
+    for (n, line) in content.lines().enumerate() {
+	let line = line.trim();
+	if let Some(expected) = line.strip_prefix("#?") {
+	    let entry = entries.last_mut().filter(|x| x.id.is_some() && x.expected.is_none())
+		.ok_or(format!("Expected response on line {} doesn't follow a numbered command!", n + 1))?;
+	    entry.expected = Some(parse_expected(expected.trim()).map_err(|e| format!("Line {}: {e}", n + 1))?);
+	} else if line.is_empty() || line.starts_with('#') {
+	    continue;
+	} else {
+	    let (id, command) = match line.split_once(char::is_whitespace) {
+		Some((id, command)) if id.parse::<u32>().is_ok() => (id.parse::<u32>().ok(), command.trim()),
+		_ => (None, line),
+	    };
+	    entries.push(TestEntry { line: n + 1, id: id, command: command.to_string(), expected: None });
+	}
+    }
+    Ok(entries)
+}
+
+
+fn parse_expected(s: &str) -> Result<Expected, String> {
+    let (s, known_failure) = match s.strip_suffix('*') {
+	Some(s) => (s.trim_end(), true),
+	None => (s.trim_end_matches('&').trim_end(), false),
+    };
+    let pattern = s.strip_prefix('[').and_then(|x| x.strip_suffix(']'))
+	.ok_or(format!("Expected response '{s}' is not in brackets!"))?;
+    let (pattern, negated) = match pattern.strip_prefix('!') {
+	Some(p) => (p, true),
+	None => (pattern, false),
+    };
+    let regex = Regex::new(&format!("(?s)^(?:{pattern})$")).map_err(|e| format!("Illegal pattern '{pattern}': {e}"))?;
+    Ok(Expected { text: s.to_string(), regex: regex, negated: negated, known_failure: known_failure })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_engine::MockEngine;
+    use std::env;
+
+    #[test]
+    fn regression() {
+	let dir = env::temp_dir().join(format!("go-game-regress-{}", std::process::id()));
+	fs::create_dir_all(&dir).unwrap();
+	let path = dir.join("test.tst");
+	fs::write(&path, "# Tactics\nloadsgf games/a.sgf 5\n\n1 reg_genmove black\n#? [Q16|D4]\n2 reg_genmove white\n#? [!Q16]\n3 reg_genmove black\n#? [D4]*\n4 frobnicate\n#? [.*]\n").unwrap();
+
+	let mut engine = MockEngine::new();
+	let report = run_test_file(&mut engine, &path).unwrap();
+	fs::remove_dir_all(&dir).unwrap();
+
+	let passed: Vec<_> = report.results.iter().map(|x| x.passed).collect();
+	assert_eq!(passed, vec![true, false, false, false]);
+	assert_eq!(report.count_passed(), 1);
+	assert_eq!(report.count_unexpected_failures(), 2);
+	assert_eq!(report.count_unexpected_passes(), 0);
+	assert!(report.results[3].response.is_err());
+	assert_eq!(engine.get_commands()[0], format!("loadsgf {} 5", dir.join("games/a.sgf").display()));
+	let text = report.to_string();
+	assert!(text.contains("line 6: 2 reg_genmove white: FAILED"), "{text}");
+	assert!(text.contains("4 tests, 1 passed, 2 unexpected failures"));
+
+	assert!(parse_test_file("#? [A1]").is_err());
+	assert!(parse_expected("[(]").is_err());
+    }
+}
@@ -0,0 +1,398 @@
+// Recording of GTP sessions to transcript files and an engine that
+// replays them, also from the command line with "replay <transcript>".
+//
+// A transcript file starts with a header followed by records:
+//
+//   engine <engine name>
+//   command <command line that started the engine>
+//   > <time> <id> <command>
+//   < <time> <response>
+//   ! <time> <error kind> <error message>
+//
+// Time is seconds since the Unix epoch. Lines of multi-line
+// responses and error messages after the first one start with "| ".
+
+use crate::gtp::*;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+pub struct Transcript {
+    path: PathBuf,
+    file: File,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TranscriptRecord {
+    Command(f64, String), // Command including its id.
+    Response(f64, String), // Response including its status and id.
+    Error(f64, String, String), // Error kind and message.
+}
+
+pub struct RecordedSession {
+    engine_name: String,
+    command: String,
+    records: Vec<TranscriptRecord>,
+}
+
+// Engine that answers commands with the responses of a recorded
+// session. Commands have to be sent in the recorded order.
+pub struct ReplayEngine {
+    id: u32,
+    records: RefCell<VecDeque<TranscriptRecord>>,
+    outcomes: RefCell<VecDeque<Result<String, GtpError>>>, // Outcomes of the last written command.
+}
+
+
+impl Transcript {
+    // Creates a new transcript file in dir. Files are named after the
+    // engine and the creation time.
+    pub fn create(dir: &Path, engine_name: &str, command: &str) -> Result<Self, String> {
+	fs::create_dir_all(dir).map_err(|e| format!("Cannot create transcript directory {dir:?}: {e}"))?;
+
+	let name: String = engine_name.chars()
+	    .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' })
+	    .collect();
+	let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+
+	for n in 0.. {
+	    let suffix = if n == 0 { String::new() } else { format!("-{n}") };
+	    let path = dir.join(format!("{name}-{millis}{suffix}.gtp"));
+	    match OpenOptions::new().write(true).create_new(true).open(&path) {
+		Ok(file) => {
+		    let mut transcript = Self { path: path, file: file };
+		    transcript.write(&format!("engine {engine_name}\ncommand {command}\n"));
+		    return Ok(transcript);
+		},
+		Err(e) if e.kind() == ErrorKind::AlreadyExists => { continue; },
+		Err(e) => { return Err(format!("Cannot create transcript {path:?}: {e}")); },
+	    }
+	}
+	unreachable!()
+    }
+
+    pub fn get_path(&self) -> &Path {
+	&self.path
+    }
+
+    pub fn record_command(&mut self, command: &str) {
+	self.write(&format!("> {:.3} {}\n", now(), escape_lines(command)));
+    }
+
+    pub fn record_response(&mut self, response: &str) {
+	self.write(&format!("< {:.3} {}\n", now(), escape_lines(response)));
+    }
+
+    pub fn record_error(&mut self, e: &GtpError) {
+	let (kind, message) = error_to_kind(e);
+	self.write(&format!("! {:.3} {kind} {}\n", now(), escape_lines(message)));
+    }
+
+    // Records are written right away so that they survive a crash.
+    fn write(&mut self, s: &str) {
+	if let Err(e) = self.file.write_all(s.as_bytes()) {
+	    println!("Failed to write transcript {:?}: {e}", self.path);
+	}
+    }
+}
+
+
+impl RecordedSession {
+    pub fn load(path: &Path) -> Result<Self, String> {
+	let content = fs::read_to_string(path).map_err(|e| format!("Cannot read transcript {path:?}: {e}"))?;
+	Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+	let mut engine_name = String::new();
+	let mut command = String::new();
+	let mut records = Vec::new();
+
+	for (n, line) in content.lines().enumerate() {
+	    let illegal = || format!("Illegal transcript line {}: {line}", n + 1);
+	    let (tag, rest) = line.split_once(' ').unwrap_or((line, ""));
+	    match tag {
+		"engine" => { engine_name = rest.to_string(); },
+		"command" => { command = rest.to_string(); },
+		">" | "<" | "!" => {
+		    let (time, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+		    let time = time.parse::<f64>().map_err(|_| illegal())?;
+		    records.push(match tag {
+			">" => TranscriptRecord::Command(time, rest.to_string()),
+			"<" => TranscriptRecord::Response(time, rest.to_string()),
+			_ => {
+			    let (kind, message) = rest.split_once(' ').unwrap_or((rest, ""));
+			    TranscriptRecord::Error(time, kind.to_string(), message.to_string())
+			},
+		    });
+		},
+		"|" => {
+		    match records.last_mut() {
+			Some(TranscriptRecord::Command(_, s))
+			    | Some(TranscriptRecord::Response(_, s))
+			    | Some(TranscriptRecord::Error(_, _, s)) => {
+				s.push('\n');
+				s.push_str(rest);
+			    },
+			None => { return Err(illegal()); },
+		    }
+		},
+		_ => { return Err(illegal()); },
+	    }
+	}
+
+	Ok(Self {
+	    engine_name: engine_name,
+	    command: command,
+	    records: records,
+	})
+    }
+
+    pub fn get_engine_name(&self) -> &str {
+	&self.engine_name
+    }
+
+    pub fn get_command(&self) -> &str {
+	&self.command
+    }
+
+    pub fn get_records(&self) -> &[TranscriptRecord] {
+	&self.records
+    }
+}
+
+
+impl ReplayEngine {
+    pub fn new(session: RecordedSession) -> Self {
+	Self {
+	    id: 0,
+	    records: RefCell::new(session.records.into()),
+	    outcomes: RefCell::new(VecDeque::new()),
+	}
+    }
+}
+
+
+impl GTPEngineRaw for ReplayEngine {
+    fn gen_command_id(&mut self) -> u32 {
+	self.id += 1;
+	self.id
+    }
+
+    fn write_to_engine(&self, s: &str) -> Result<(), GtpError> {
+	let (id, command) = split_id(s.trim());
+	let mut records = self.records.borrow_mut();
+
+	// Skip to the next recorded command.
+	let recorded = loop {
+	    match records.pop_front() {
+		Some(TranscriptRecord::Command(_, s)) => { break s; },
+		Some(_) => { continue; },
+		None => { return Err(GtpError::Protocol(format!("Transcript has no command left for '{command}'!"))); },
+	    }
+	};
+	let (recorded_id, recorded) = split_id(&recorded);
+	if recorded != command {
+	    return Err(GtpError::Protocol(format!("Command '{command}' doesn't match the transcript command '{recorded}'!")));
+	}
+
+	let mut outcomes = self.outcomes.borrow_mut();
+	outcomes.clear();
+	while let Some(TranscriptRecord::Response(..) | TranscriptRecord::Error(..)) = records.front() {
+	    match records.pop_front() {
+		Some(TranscriptRecord::Response(_, s)) => {
+		    outcomes.push_back(Ok(renumber_response(&s, recorded_id, id)));
+		},
+		Some(TranscriptRecord::Error(_, kind, message)) => {
+		    outcomes.push_back(Err(error_from_kind(&kind, &message)));
+		},
+		_ => (),
+	    }
+	}
+	Ok(())
+    }
+
+    fn read_from_engine(&mut self) -> Result<String, GtpError> {
+	self.outcomes.borrow_mut().pop_front()
+	    .unwrap_or(Err(GtpError::Protocol("Transcript has no response left!".to_string())))
+    }
+}
+
+
+impl GTPEngineMinimal for ReplayEngine {}
+
+
+impl GTPEngineTournament for ReplayEngine {}
+
+
+impl GTPEngineRegression for ReplayEngine {}
+
+
+impl GTPEngineExtendedCorePlay for ReplayEngine {}
+
+
+impl GTPEngineExtendedTournament for ReplayEngine {}
+
+
+impl GTPEngineDebug for ReplayEngine {}
+
+
+// Runs "replay <transcript>" from the command line. Prints what the
+// replayed engine answers to each recorded command, e.g. to look into
+// an engine bug without the engine.
+pub fn run_from_args(args: &[String]) -> Result<(), String> {
+    let path = match args {
+	[path] => Path::new(path),
+	_ => { return Err("Usage: go-game replay <transcript>".to_string()); },
+    };
+
+    let session = RecordedSession::load(path)?;
+    println!("Replaying {} started with '{}'.", session.get_engine_name(), session.get_command());
+    for (command, outcome) in replay(session) {
+	match outcome {
+	    Ok(s) => { println!("{command}: {s}"); },
+	    Err(e) => { println!("{command} failed: {e}"); },
+	}
+    }
+    Ok(())
+}
+
+
+// Sends the recorded commands in order to a ReplayEngine and returns
+// each command with its outcome.
+pub fn replay(session: RecordedSession) -> Vec<(String, Result<String, GtpError>)> {
+    let commands: Vec<String> = session.get_records().iter()
+	.filter_map(|x| match x {
+	    TranscriptRecord::Command(_, s) => Some(split_id(s).1.to_string()),
+	    _ => None,
+	})
+	.collect();
+
+    let mut engine = ReplayEngine::new(session);
+    commands.into_iter()
+	.map(|command| {
+	    let outcome = engine.send_command(&command);
+	    (command, outcome)
+	})
+	.collect()
+}
+
+
+fn now() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()
+}
+
+
+fn escape_lines(s: &str) -> String {
+    s.replace('\n', "\n| ")
+}
+
+
+// Gives the response of the recorded command the id of the new
+// command. Late responses of earlier commands keep their distance so
+// that they are still discarded as stale.
+fn renumber_response(response: &str, recorded_id: &str, id: &str) -> String {
+    let status_len = response.chars().next().map_or(0, |c| c.len_utf8());
+    let (status, rest) = response.split_at(status_len);
+    let rest_without_id = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+    let response_id = &rest[..rest.len() - rest_without_id.len()];
+
+    let new_id = match (response_id.parse::<u32>(), recorded_id.parse::<u32>(), id.parse::<u32>()) {
+	(Ok(r), Ok(c), Ok(n)) => n.saturating_sub(c.saturating_sub(r)).to_string(),
+	_ => id.to_string(),
+    };
+    format!("{status}{new_id}{rest_without_id}")
+}
+
+
+// Splits "<id> <command>" into id and command. Id is empty if the
+// command doesn't have one.
+fn split_id(s: &str) -> (&str, &str) {
+    match s.split_once(' ') {
+	Some((id, command)) if id.parse::<u32>().is_ok() => (id, command),
+	_ => ("", s),
+    }
+}
+
+
+fn error_to_kind(e: &GtpError) -> (&str, &str) {
+    match e {
+	GtpError::EngineFailure(s) => ("EngineFailure", s),
+	GtpError::Io(s) => ("Io", s),
+	GtpError::ProcessExited(s) => ("ProcessExited", s),
+	GtpError::Timeout(s) => ("Timeout", s),
+	GtpError::Protocol(s) => ("Protocol", s),
+	GtpError::Parse(s) => ("Parse", s),
+    }
+}
+
+
+fn error_from_kind(kind: &str, message: &str) -> GtpError {
+    let message = message.to_string();
+    match kind {
+	"EngineFailure" => GtpError::EngineFailure(message),
+	"Io" => GtpError::Io(message),
+	"ProcessExited" => GtpError::ProcessExited(message),
+	"Timeout" => GtpError::Timeout(message),
+	"Parse" => GtpError::Parse(message),
+	_ => GtpError::Protocol(message),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn record_and_replay() {
+	let dir = env::temp_dir().join(format!("go-game-transcript-{}", std::process::id()));
+	let mut transcript = Transcript::create(&dir, "fake engine", "fake_gtp_engine --log x").unwrap();
+	transcript.record_command("1 name");
+	transcript.record_response("=1 Fake");
+	transcript.record_command("2 showboard");
+	transcript.record_response("=2 \n   A B\n 1 X . 1");
+	transcript.record_command("3 genmove B");
+	transcript.record_error(&GtpError::Timeout("No response.".to_string()));
+	transcript.record_response("=3 D4");
+	transcript.record_command("4 genmove W");
+	transcript.record_response("=4 Q16");
+
+	let session = RecordedSession::load(transcript.get_path()).unwrap();
+	assert_eq!(session.get_engine_name(), "fake engine");
+	assert_eq!(session.get_command(), "fake_gtp_engine --log x");
+	assert_eq!(session.get_records().len(), 9);
+	fs::remove_dir_all(&dir).unwrap();
+
+	let mut engine = ReplayEngine::new(session);
+	assert_eq!(engine.name().unwrap(), "Fake");
+	assert_eq!(engine.showboard().unwrap(), "\n   A B\n 1 X . 1".trim_start());
+	assert!(matches!(engine.genmove(Color::Black), Err(GtpError::Timeout(_))));
+	// Commands have to follow the recorded order.
+	assert!(matches!(engine.genmove(Color::Black), Err(GtpError::Protocol(_))));
+    }
+
+    #[test]
+    fn replay_late_response() {
+	let session = RecordedSession::parse("engine e\n> 1 1 genmove B\n! 2 Timeout slow\n> 4 2 genmove W\n< 5 =1 D4\n< 5 =2 Q16\n").unwrap();
+	let mut engine = ReplayEngine::new(session);
+	assert!(matches!(engine.genmove(Color::Black), Err(GtpError::Timeout(_))));
+	assert_eq!(engine.genmove(Color::White).unwrap(), GenMove::Play(Vertex::Coordinate(15, 15)));
+    }
+
+    #[test]
+    fn replay_commands() {
+	let session = RecordedSession::parse("engine e\ncommand e --x\n> 1 1 genmove B\n! 2 Timeout slow\n> 4 2 name\n< 5 =1 D4\n< 5 =2 e\n").unwrap();
+	let outcomes = replay(session);
+	assert_eq!(outcomes.len(), 2);
+	assert_eq!(outcomes[0].0, "genmove B");
+	assert!(matches!(outcomes[0].1, Err(GtpError::Timeout(_))));
+	assert_eq!(outcomes[1].0, "name");
+	assert_eq!(outcomes[1].1.as_deref().unwrap(), "e");
+    }
+}
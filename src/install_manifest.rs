@@ -0,0 +1,177 @@
+// Record of the KataGo installs, kept as TOML in the install directory.
+// Versions and backends are installed side by side, one of them is
+// active:
+//
+//   [[installs]]
+//   version = "v1.15.3"
+//   backend = "eigen"
+//   dir = "katago-v1.15.3-eigen-linux-x64"
+//   models = ["b18c384nbt-humanv0.bin.gz"]
+//   installed_at = 1760000000   # Seconds since the Unix epoch.
+//   active = true
+//
+// A KataGo that was installed otherwise, e.g. by a package manager, is
+// recorded with the "external" backend and the path of its executable
+// in "exe".
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+pub const MANIFEST_FILE: &str = "manifest.toml";
+
+pub const EXTERNAL_BACKEND: &str = "external";
+
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstallManifest {
+    #[serde(default)]
+    pub installs: Vec<InstallRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstallRecord {
+    pub version: String,
+    pub backend: String, // Name of the backend.
+    pub dir: String, // Relative to the install directory, absolute for external installs.
+    #[serde(default)]
+    pub models: Vec<String>, // File names in the networks directory.
+    pub installed_at: u64,
+    #[serde(default)]
+    pub active: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exe: Option<PathBuf>, // Executable of an external install.
+}
+
+
+impl InstallManifest {
+    // A missing file is an empty manifest.
+    pub fn load(path: &Path) -> Result<Self, String> {
+	if !path.exists() {
+	    return Ok(Self::default());
+	}
+	let content = fs::read_to_string(path).map_err(|e| format!("Cannot read install manifest {path:?}: {e}"))?;
+	toml::from_str(&content).map_err(|e| format!("Cannot parse install manifest {path:?}: {e}"))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+	if let Some(dir) = path.parent() {
+	    fs::create_dir_all(dir).map_err(|e| format!("Cannot create directory {dir:?}: {e}"))?;
+	}
+	let content = toml::to_string(self).map_err(|e| format!("Cannot serialize install manifest: {e}"))?;
+	fs::write(path, content).map_err(|e| format!("Cannot write install manifest {path:?}: {e}"))
+    }
+
+    pub fn get_active(&self) -> Option<&InstallRecord> {
+	self.installs.iter().find(|x| x.active)
+    }
+
+    pub fn get_install(&self, version: &str, backend: &str) -> Option<&InstallRecord> {
+	self.installs.iter().find(|x| x.version == version && x.backend == backend)
+    }
+
+    // Adds the install as the active one, replacing a record of the
+    // same version and backend. Newest versions come first.
+    pub fn add(&mut self, record: InstallRecord) {
+	let (version, backend) = (record.version.clone(), record.backend.clone());
+	self.installs.retain(|x| x.version != version || x.backend != backend);
+	self.installs.push(record);
+	self.installs.sort_by(|a, b| compare_versions(&b.version, &a.version).then(a.backend.cmp(&b.backend)));
+	self.activate(&version, &backend).unwrap();
+    }
+
+    pub fn activate(&mut self, version: &str, backend: &str) -> Result<(), String> {
+	if self.get_install(version, backend).is_none() {
+	    return Err(format!("KataGo {version} {backend} is not installed!"));
+	}
+	for record in &mut self.installs {
+	    record.active = record.version == version && record.backend == backend;
+	}
+	Ok(())
+    }
+}
+
+
+impl InstallRecord {
+    // Date of the install in UTC, e.g. "2024-10-05".
+    pub fn get_date(&self) -> String {
+	// Days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+	let z = (self.installed_at / 86400) as i64 + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z.rem_euclid(146097);
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+	format!("{year:04}-{month:02}-{day:02}")
+    }
+}
+
+
+// Compares versions like "v1.15.3" by their numbers.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let numbers = |s: &str| -> Vec<u32> {
+	s.trim_start_matches('v').split('.').map(|x| x.parse().unwrap_or(0)).collect()
+    };
+    numbers(a).cmp(&numbers(b))
+}
+
+
+// Seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.as_secs())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn record(version: &str, backend: &str) -> InstallRecord {
+	InstallRecord {
+	    version: version.to_string(),
+	    backend: backend.to_string(),
+	    dir: format!("katago-{version}-{backend}-linux-x64"),
+	    models: vec!["b18c384nbt-humanv0.bin.gz".to_string()],
+	    installed_at: 1728086400,
+	    active: false,
+	    exe: None,
+	}
+    }
+
+    #[test]
+    fn manifest() {
+	let path = env::temp_dir().join(format!("go-game-manifest-{}", std::process::id())).join(MANIFEST_FILE);
+	assert_eq!(InstallManifest::load(&path).unwrap(), InstallManifest::default());
+
+	let mut manifest = InstallManifest::default();
+	manifest.add(record("v1.9.1", "eigen"));
+	manifest.add(record("v1.15.3", "eigen"));
+	manifest.add(record("v1.15.3", "opencl"));
+	manifest.add(record("v1.15.3", "eigen"));
+	let mut external = record("v1.14.1", EXTERNAL_BACKEND);
+	external.exe = Some(PathBuf::from("/usr/bin/katago"));
+	manifest.add(external);
+	manifest.activate("v1.15.3", "eigen").unwrap();
+	let installs: Vec<_> = manifest.installs.iter().map(|x| (x.version.as_str(), x.backend.as_str(), x.active)).collect();
+	assert_eq!(installs, vec![("v1.15.3", "eigen", true), ("v1.15.3", "opencl", false), ("v1.14.1", EXTERNAL_BACKEND, false), ("v1.9.1", "eigen", false)]);
+
+	manifest.activate("v1.9.1", "eigen").unwrap();
+	assert!(manifest.activate("v1.9.1", "opencl").is_err());
+	manifest.save(&path).unwrap();
+	let loaded = InstallManifest::load(&path).unwrap();
+	fs::remove_dir_all(path.parent().unwrap()).unwrap();
+	assert_eq!(loaded, manifest);
+	assert_eq!(loaded.get_active().unwrap().version, "v1.9.1");
+
+	assert_eq!(compare_versions("v1.9.1", "v1.15.3"), Ordering::Less);
+	assert_eq!(compare_versions("v1.15.3", "1.15.3"), Ordering::Equal);
+	assert_eq!(record("v1.15.3", "eigen").get_date(), "2024-10-05");
+    }
+}
@@ -0,0 +1,182 @@
+// KataGo config files, lines of "key = value" with '#' comments.
+// Editing keeps the comments and the order of the lines, so that a
+// config generated by KataGo stays readable.
+
+use std::fs;
+use std::path::Path;
+
+
+// Settings offered in the engine panel, with a short description.
+pub const COMMON_SETTINGS: [(&str, &str); 7] = [
+    ("maxVisits", "Visits per move"),
+    ("maxTime", "Seconds per move"),
+    ("numSearchThreads", "Search threads"),
+    ("rules", "Rules, e.g. tromp-taylor, chinese, japanese"),
+    ("ponderingEnabled", "Ponder on the opponent's time (true/false)"),
+    ("humanSLProfile", "Human profile, e.g. rank_5k, preaz_1d"),
+    ("resignThreshold", "Resign below this winrate, e.g. -0.90"),
+];
+
+// Used when the KataGo release has no default_gtp.cfg.
+const GTP_TEMPLATE: &str = "\
+# KataGo GTP config generated by go-game.
+# https://github.com/lightvector/KataGo/blob/master/cpp/configs/gtp_example.cfg
+
+logDir = gtp_logs
+logAllGTPCommunication = false
+logSearchInfo = false
+
+rules = tromp-taylor
+allowResignation = true
+resignThreshold = -0.90
+resignConsecTurns = 3
+
+ponderingEnabled = false
+maxVisits = 500
+maxTime = 10
+numSearchThreads = 8
+";
+
+
+// The engines of KataGo have a config each, so that the human engine
+// can play at a rank while the analysis engine reads deeply.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ConfigKind {
+    Analysis,
+    Human,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KataGoConfig {
+    lines: Vec<String>,
+}
+
+
+impl ConfigKind {
+    pub const ALL: [ConfigKind; 2] = [ConfigKind::Analysis, ConfigKind::Human];
+
+    pub fn get_name(&self) -> &'static str {
+	match self {
+	    ConfigKind::Analysis => "analysis",
+	    ConfigKind::Human => "human",
+	}
+    }
+
+    pub fn get_file_name(&self) -> String {
+	format!("{}.cfg", self.get_name())
+    }
+
+    // Settings that make the config fit the engine, applied when it
+    // is generated.
+    pub fn get_defaults(&self) -> &'static [(&'static str, &'static str)] {
+	match self {
+	    ConfigKind::Analysis => &[],
+	    ConfigKind::Human => &[
+		("humanSLProfile", "rank_5k"),
+		("humanSLChosenMoveProp", "1.0"),
+		("humanSLChosenMoveIgnorePass", "true"),
+		("maxVisits", "40"),
+	    ],
+	}
+    }
+}
+
+
+impl KataGoConfig {
+    pub fn from_string(s: &str) -> Self {
+	Self {
+	    lines: s.lines().map(|x| x.to_string()).collect(),
+	}
+    }
+
+    pub fn template() -> Self {
+	Self::from_string(GTP_TEMPLATE)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+	let s = fs::read_to_string(path).map_err(|e| format!("Cannot read KataGo config {path:?}: {e}"))?;
+	Ok(Self::from_string(&s))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+	if let Some(dir) = path.parent() {
+	    fs::create_dir_all(dir).map_err(|e| format!("Cannot create directory {dir:?}: {e}"))?;
+	}
+	fs::write(path, self.to_string()).map_err(|e| format!("Cannot write KataGo config {path:?}: {e}"))
+    }
+
+    // Value of the last line setting the key, as KataGo reads it.
+    pub fn get(&self, key: &str) -> Option<String> {
+	self.lines.iter().rev().find_map(|x| match parse_line(x) {
+	    Some((k, v)) if k == key => Some(v.to_string()),
+	    _ => None,
+	})
+    }
+
+    // Replaces the value of the key, keeping a comment at the end of
+    // the line, or appends the key. An empty value removes the key.
+    pub fn set(&mut self, key: &str, value: &str) {
+	let value = value.trim();
+	let index = self.lines.iter().rposition(|x| parse_line(x).is_some_and(|(k, _)| k == key));
+	match (index, value.is_empty()) {
+	    (Some(i), true) => { self.lines.remove(i); },
+	    (Some(i), false) => {
+		let comment = self.lines[i].find('#').map(|j| self.lines[i][j..].to_string());
+		self.lines[i] = match comment {
+		    Some(comment) => format!("{key} = {value}  {comment}"),
+		    None => format!("{key} = {value}"),
+		};
+	    },
+	    (None, true) => {},
+	    (None, false) => { self.lines.push(format!("{key} = {value}")); },
+	}
+    }
+}
+
+
+impl std::fmt::Display for KataGoConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+	for line in &self.lines {
+	    writeln!(f, "{line}")?;
+	}
+	Ok(())
+    }
+}
+
+
+// Key and value of a setting line, None for comments and empty lines.
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    let line = match line.find('#') {
+	Some(i) => &line[..i],
+	None => line,
+    };
+    let (key, value) = line.split_once('=')?;
+    let key = key.trim();
+    if key.is_empty() {
+	return None;
+    }
+    Some((key, value.trim()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config() {
+	let mut config = KataGoConfig::from_string("# Search\nmaxVisits = 100  # Per move\n# numSearchThreads = 4\nrules = japanese\n");
+	assert_eq!(config.get("maxVisits").as_deref(), Some("100"));
+	assert_eq!(config.get("numSearchThreads"), None);
+
+	config.set("maxVisits", "800");
+	config.set("numSearchThreads", "2");
+	config.set("rules", "");
+	assert_eq!(config.to_string(), "# Search\nmaxVisits = 800  # Per move\n# numSearchThreads = 4\nnumSearchThreads = 2\n");
+	assert_eq!(KataGoConfig::from_string(&config.to_string()), config);
+
+	let template = KataGoConfig::template();
+	assert_eq!(template.get("rules").as_deref(), Some("tromp-taylor"));
+	assert_eq!(template.get("humanSLProfile"), None);
+    }
+}
@@ -1,102 +1,495 @@
 use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::{Arc, Mutex, MutexGuard};
-use std::sync::mpsc::Receiver;
+use std::sync::mpsc::{Receiver, TryRecvError};
 use std::thread;
-use reqwest;
+use std::time::{Duration, Instant};
 use std::fs;
-use std::io;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::collections::HashMap;
 use std::error::Error;
 use zip::ZipArchive;
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
 use crate::child_process_engine::ChildProcessEngine;
-use crate::gtp::GTPEngineMinimal;
-use crate::smart_child::{SmartChild};
+use crate::game_engine::GameEngine;
+use crate::gtp::{GTPEngineMinimal, GTPEngineRaw, GtpError};
+use crate::smart_child::{LaunchSpec, SmartChild};
+use crate::network_manager::{is_network_file, NetworkInfo, NetworkManager, NETWORK_EXTENSIONS};
+use crate::katago_config::{ConfigKind, KataGoConfig};
+use crate::install_manifest::{self, InstallManifest, InstallRecord, EXTERNAL_BACKEND, MANIFEST_FILE};
+
+
+// Version that installs and upgrades download.
+pub const KATAGO_VERSION: &str = "v1.15.3";
+
+// File in the install directory that keeps the selected backend.
+const BACKEND_FILE: &str = "backend";
+
+// Directory of the networks in the install directory.
+const NETWORKS_DIR: &str = "networks";
+
+// Directories of the engine configs and the logs KataGo writes, in the
+// install directory. Configs are kept when reinstalling.
+const CONFIGS_DIR: &str = "configs";
+const LOGS_DIR: &str = "logs";
+
+// Error of an operation that stopped because of the kill signal.
+pub const CANCELLED: &str = "Installation cancelled!";
+
+// Time the test gives KataGo to load the model, which includes OpenCL
+// tuning on the first start.
+const TEST_TIMEOUT: Duration = Duration::from_secs(600);
+
+// SHA-256 digests of the release artifacts the installer downloads,
+// by file name. Every backend archive of KATAGO_VERSION and both
+// default networks belong here. An artifact that is not listed is
+// checked for integrity instead, reading every entry of an archive and
+// the whole stream of a network, and then against the digest recorded
+// next to it in a ".sha256" file. That path is meant for the networks
+// users add.
+const SHA256_DIGESTS: &[(&str, &str)] = &[];
+
+// Directories where package managers and manual installs put KataGo,
+// searched besides PATH.
+const COMMON_LOCATIONS: &[&str] = &["/usr/local/bin", "/usr/bin", "/opt/homebrew/bin", "/snap/bin", "/opt/katago", "C:\\Program Files\\KataGo"];
+
+// Minimum time between download progress reports.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
 
 
 #[derive(Clone)]
 pub struct KataGoInstaller {
-    path_info: Option<PathInfo>,
+    install_dir: PathBuf,
+    backend: Arc<Mutex<Option<Backend>>>, // Shared by clones. None if the OS is not supported.
+    version: Arc<Mutex<String>>, // Of the active install, shared by clones.
+    external: Arc<Mutex<Option<InstallRecord>>>, // Active install not made by the app, shared by clones.
+    progress: Arc<Mutex<Option<InstallProgress>>>, // Of the ongoing operation, shared by clones.
+    networks: NetworkManager,
     mutex: Arc<Mutex<()>>,
 }
 
+#[derive(Clone, Debug, Default)]
+pub struct InstallProgress {
+    pub step: String,
+    pub download: Option<DownloadProgress>, // Of the step, if it downloads.
+}
+
+#[derive(Clone, Debug)]
+pub struct DownloadProgress {
+    pub done: u64, // Bytes.
+    pub total: Option<u64>, // None if the server doesn't tell.
+    pub rate: f64, // Bytes per second.
+}
+
+// Neural net backend of a KataGo build. Each backend is a separate
+// release archive and is installed in its own directory.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Backend {
+    EigenAvx2, // CPU with AVX2.
+    Eigen, // Any CPU.
+    OpenCL,
+    Cuda,
+    TensorRT,
+}
+
 #[derive(Clone)]
 struct PathInfo {
+    backend: Backend,
     katago_url: String,
     katago_zip: PathBuf,
+    katago_sha256: Option<String>, // None if not pinned in SHA256_DIGESTS.
     katago_dir: PathBuf,
     katago_exe: PathBuf,
     analysis_model_url: String,
     analysis_model: PathBuf,
+    analysis_model_sha256: Option<String>,
     human_model_url: String,
     human_model: PathBuf,
+    human_model_sha256: Option<String>,
+    external: bool, // The executable and models are of an external install.
 }
 
 
 impl KataGoInstaller {
+    // Uses the backend selected before, or the preferred one among the
+    // detected backends.
     pub fn new(install_dir: &Path) -> Self {
-	let url_and_dir = if cfg!(target_os = "windows") {
-	    Some(("https://github.com/lightvector/KataGo/releases/download/v1.15.3/katago-v1.15.3-opencl-windows-x64.zip",
-		  "katago-v1.15.3-opencl-windows-x64"))
+	let os = if cfg!(target_os = "windows") {
+	    Some("windows")
 	} else if cfg!(target_os = "linux") {
-	    Some(("https://github.com/lightvector/KataGo/releases/download/v1.15.3/katago-v1.15.3-opencl-linux-x64.zip",
-		  "katago-v1.15.3-opencl-linux-x64"))
+	    Some("linux")
 	} else {
 	    println!("KataGo doesn't support target_os '{}'!", std::env::consts::OS);
 	    None
 	};
 
-	let analysis_model_url = "https://github.com/lightvector/KataGo/releases/download/v1.4.5/g170e-b20c256x2-s5303129600-d1228401921.bin.gz";
-	let analysis_model = "g170e-b20c256x2-s5303129600-d1228401921.bin.gz";
-	let human_model_url = "https://github.com/lightvector/KataGo/releases/download/v1.15.0/b18c384nbt-humanv0.bin.gz";
-	let human_model = "b18c384nbt-humanv0.bin.gz";
+	let saved = fs::read_to_string(install_dir.join(BACKEND_FILE)).ok()
+	    .and_then(|s| Backend::from_name(s.trim()));
+	// Installs before the backend choice were the OpenCL build. Keeping
+	// to it spares a download, e.g. of TensorRT on NVIDIA machines.
+	let opencl = os.map(|os| install_dir.join(Backend::OpenCL.archive_name(os, KATAGO_VERSION)))
+	    .filter(|x| x.exists())
+	    .map(|_| Backend::OpenCL);
+	let backend = os.and(saved.or(opencl).or_else(|| Backend::detect().first().copied()));
 
-	let path_info = url_and_dir.map(|(url, dir)| {
-	    let zip = install_dir.join(format!("{dir}.zip"));
-	    let dir = install_dir.join(dir);
-	    
-	    let mut exe = String::from("katago");
-	    if cfg!(windows) {
-		exe.push_str(".exe");
+	// Installs before the network manager kept the networks in the
+	// install directory.
+	let networks = NetworkManager::new(&install_dir.join(NETWORKS_DIR));
+	if let Err(s) = networks.migrate(install_dir) {
+	    println!("Networks couldn't be migrated: {s}");
+	}
+
+	let manifest = InstallManifest::load(&install_dir.join(MANIFEST_FILE)).unwrap_or_else(|s| {
+	    println!("{s}");
+	    InstallManifest::default()
+	});
+	let version = match manifest.get_active() {
+	    Some(record) => record.version.clone(),
+	    None => KATAGO_VERSION.to_string(),
+	};
+	let external = manifest.get_active().filter(|x| x.exe.is_some()).cloned();
+
+	let installer = Self {
+	    install_dir: install_dir.to_path_buf(),
+	    backend: Arc::new(Mutex::new(backend)),
+	    version: Arc::new(Mutex::new(version)),
+	    external: Arc::new(Mutex::new(external)),
+	    progress: Arc::new(Mutex::new(None)),
+	    networks: networks,
+	    mutex: Arc::new(Mutex::new(())),
+	};
+	if let Ok(path_info) = installer.get_path_info() {
+	    if let Err(e) = recover_swap_in(&path_info.katago_dir) {
+		println!("Interrupted KataGo install couldn't be recovered: {e}");
 	    }
-	    let exe = dir.join(exe);
-	    
-	    PathInfo {
-		katago_url: url.to_string(),
-		katago_zip: zip,
-		katago_dir: dir,
-		katago_exe: exe,
-		analysis_model_url: analysis_model_url.to_string(),
-		analysis_model: install_dir.join(analysis_model),
-		human_model_url: human_model_url.to_string(),
-		human_model: install_dir.join(human_model),
+	}
+	// Installs before the manifest have no record.
+	if manifest.installs.is_empty() && installer.is_installed_without_lock() {
+	    if let Err(s) = installer.record_install() {
+		println!("KataGo install couldn't be recorded: {s}");
 	    }
+	}
+	installer
+    }
+
+    pub fn get_manifest(&self) -> Result<InstallManifest, String> {
+	InstallManifest::load(&self.install_dir.join(MANIFEST_FILE))
+    }
+
+    // Records the install of the current version and backend as the
+    // active one.
+    fn record_install(&self) -> Result<(), String> {
+	let pi = self.get_path_info()?;
+	let file_name = |x: &PathBuf| x.file_name().unwrap().to_string_lossy().to_string();
+	let mut manifest = self.get_manifest()?;
+	manifest.add(InstallRecord {
+	    version: self.get_version(),
+	    backend: pi.backend.get_name().to_string(),
+	    dir: file_name(&pi.katago_dir),
+	    models: vec![file_name(&pi.analysis_model), file_name(&pi.human_model)],
+	    installed_at: install_manifest::now(),
+	    active: true,
+	    exe: None,
 	});
+	manifest.save(&self.install_dir.join(MANIFEST_FILE))
+    }
 
-	Self {
-	    path_info: path_info,
-	    mutex: Arc::new(Mutex::new(())),
+    pub fn get_version(&self) -> String {
+	self.version.lock().unwrap().clone()
+    }
+
+    // The version to upgrade to, if the active one is older. External
+    // installs are upgraded by their package manager.
+    pub fn get_update(&self) -> Option<&'static str> {
+	if self.external.lock().unwrap().is_some() {
+	    return None;
+	}
+	let older = install_manifest::compare_versions(&self.get_version(), KATAGO_VERSION).is_lt();
+	older.then_some(KATAGO_VERSION)
+    }
+
+    // Installs the version of this app besides the active one and
+    // activates it. The active version is kept if the upgrade fails.
+    pub fn upgrade(&self, kill_signal_rx: &Receiver<()>) -> Result<(), String> {
+	let _guard = self.lock();
+	let previous = self.get_manifest()?.get_active().cloned();
+	let previous_version = self.get_version();
+	*self.version.lock().unwrap() = KATAGO_VERSION.to_string();
+	let r = self.install_steps(&HashMap::new(), kill_signal_rx)
+	    .and_then(|()| self.test_without_lock(kill_signal_rx).map(|_| ()));
+	if r.is_err() {
+	    match previous {
+		Some(record) => { self.activate_without_lock(&record.version, &record.backend)?; },
+		None => { *self.version.lock().unwrap() = previous_version; },
+	    }
+	}
+	*self.progress.lock().unwrap() = None;
+	r
+    }
+
+    // Switches to an installed version and backend, also to roll back
+    // an upgrade.
+    pub fn activate(&self, version: &str, backend: &str) -> Result<(), String> {
+	let _guard = self.lock();
+	self.activate_without_lock(version, backend)
+    }
+
+    fn activate_without_lock(&self, version: &str, backend: &str) -> Result<(), String> {
+	let mut manifest = self.get_manifest()?;
+	manifest.activate(version, backend)?;
+	let record = manifest.get_active().unwrap().clone();
+	let exe = match (&record.exe, Backend::from_name(backend)) {
+	    (Some(exe), _) => exe.clone(),
+	    (None, Some(backend)) => PathInfo::new(&self.install_dir, backend, version).katago_exe,
+	    (None, None) => { return Err(format!("Unknown KataGo backend {backend}!")); },
+	};
+	if !exe.exists() {
+	    return Err(format!("{exe:?} doesn't exist, reinstall KataGo {version}!"));
+	}
+	manifest.save(&self.install_dir.join(MANIFEST_FILE))?;
+	*self.version.lock().unwrap() = version.to_string();
+	match Backend::from_name(backend) {
+	    Some(backend) if record.exe.is_none() => {
+		*self.external.lock().unwrap() = None;
+		self.set_backend(backend)
+	    },
+	    _ => {
+		*self.external.lock().unwrap() = Some(record);
+		Ok(())
+	    },
 	}
     }
 
-    fn get_path_info(&self) -> Result<&PathInfo, String> {
-	match &self.path_info {
-	    Some(path_info) => Ok(&path_info),
+    // Executables named katago on PATH and in common locations, except
+    // the ones installed by the app.
+    pub fn find_external(&self) -> Vec<PathBuf> {
+	let mut dirs: Vec<PathBuf> = std::env::var_os("PATH").map(|x| std::env::split_paths(&x).collect()).unwrap_or_default();
+	dirs.extend(COMMON_LOCATIONS.iter().map(PathBuf::from));
+	if let Some(home) = dirs::home_dir() {
+	    dirs.extend([home.join(".local").join("bin"), home.join("katago")]);
+	}
+	let install_dir = fs::canonicalize(&self.install_dir).unwrap_or(self.install_dir.clone());
+	find_katago(&dirs).into_iter()
+	    .filter(|x| !x.starts_with(&install_dir))
+	    .collect()
+    }
+
+    // Uses a KataGo that was not installed by the app. Models are
+    // added to the network manager and become the default networks.
+    // The executable is tested before it is recorded as the active
+    // install. Returns its version.
+    pub fn adopt_external(&self, exe: &Path, models: &[PathBuf], kill_signal_rx: &Receiver<()>) -> Result<String, String> {
+	let _guard = self.lock();
+	let r = self.adopt_external_without_lock(exe, models, kill_signal_rx);
+	*self.progress.lock().unwrap() = None;
+	r
+    }
+
+    fn adopt_external_without_lock(&self, exe: &Path, models: &[PathBuf], kill_signal_rx: &Receiver<()>) -> Result<String, String> {
+	let exe = fs::canonicalize(exe).map_err(|e| format!("Cannot find {exe:?}: {e}"))?;
+	if !exe.is_file() {
+	    return Err(format!("{exe:?} is not a file!"));
+	}
+	self.set_step("Adding networks...");
+	let models = models.iter()
+	    .map(|x| self.networks.add_file(x).map(|x| x.file_name))
+	    .collect::<Result<Vec<_>, _>>()?;
+	let mut record = InstallRecord {
+	    version: String::new(), // Known after the test.
+	    backend: EXTERNAL_BACKEND.to_string(),
+	    dir: exe.parent().unwrap().to_string_lossy().to_string(),
+	    models: models,
+	    installed_at: install_manifest::now(),
+	    active: true,
+	    exe: Some(exe),
+	};
+
+	self.set_step("Testing KataGo...");
+	let previous = self.external.lock().unwrap().replace(record.clone());
+	let version = match self.test_without_lock(kill_signal_rx) {
+	    Ok(version) => version,
+	    Err(s) => {
+		*self.external.lock().unwrap() = previous;
+		return Err(s);
+	    },
+	};
+	record.version = match version.starts_with(|c: char| c.is_ascii_digit()) {
+	    true => format!("v{version}"),
+	    false => version,
+	};
+	let mut manifest = self.get_manifest()?;
+	manifest.add(record.clone());
+	manifest.save(&self.install_dir.join(MANIFEST_FILE))?;
+	*self.version.lock().unwrap() = record.version.clone();
+	*self.external.lock().unwrap() = Some(record.clone());
+	Ok(record.version)
+    }
+
+    pub fn get_networks(&self) -> &NetworkManager {
+	&self.networks
+    }
+
+    // Adds a network from a URL or a local file.
+    pub fn add_network(&self, source: &str, kill_signal_rx: &Receiver<()>) -> Result<NetworkInfo, String> {
+	let _guard = self.lock();
+	let r = self.add_network_without_lock(source, kill_signal_rx);
+	*self.progress.lock().unwrap() = None;
+	r
+    }
+
+    fn add_network_without_lock(&self, source: &str, kill_signal_rx: &Receiver<()>) -> Result<NetworkInfo, String> {
+	if !source.contains("://") {
+	    return self.networks.add_file(Path::new(source));
+	}
+	let file_name = source.rsplit('/').next().filter(|x| is_network_file(Path::new(x)))
+	    .ok_or(format!("{source} is not a network file, expected one of {NETWORK_EXTENSIONS:?}!"))?;
+	let path = self.networks.get_path(file_name);
+	fs::create_dir_all(self.networks.get_dir()).map_err(|e| e.to_string())?;
+	self.set_step(&format!("Downloading network {file_name}..."));
+	download(source, &path, None, kill_signal_rx, &mut |p| self.set_download_progress(p)).map_err(|e| e.to_string())?;
+	let info = NetworkInfo::read(&path)?;
+	if info.model_name.is_none() {
+	    self.networks.remove(file_name)?;
+	    return Err(format!("{source} is not a valid network!"));
+	}
+	Ok(info)
+    }
+
+    // Networks of the active install cannot be removed, its engines
+    // wouldn't start without them.
+    pub fn remove_network(&self, file_name: &str) -> Result<(), String> {
+	let _guard = self.lock();
+	if self.get_networks_in_use().iter().any(|x| x == file_name) {
+	    return Err(format!("{file_name} is used by the active KataGo install!"));
+	}
+	self.networks.remove(file_name)
+    }
+
+    // File names of the networks of the active install.
+    pub fn get_networks_in_use(&self) -> Vec<String> {
+	let mut in_use = match self.get_manifest() {
+	    Ok(manifest) => manifest.get_active().map(|x| x.models.clone()).unwrap_or_default(),
+	    Err(s) => {
+		println!("{s}");
+		Vec::new()
+	    },
+	};
+	if let (Ok(pi), true) = (self.get_path_info(), self.is_installed_without_lock()) {
+	    for model in [&pi.analysis_model, &pi.human_model] {
+		if let Some(name) = model.file_name() {
+		    in_use.push(name.to_string_lossy().to_string());
+		}
+	    }
+	}
+	in_use.sort();
+	in_use.dedup();
+	in_use
+    }
+
+    pub fn get_backend(&self) -> Option<Backend> {
+	*self.backend.lock().unwrap()
+    }
+
+    // Selects the backend for the next operations and remembers it
+    // for the next start.
+    pub fn set_backend(&self, backend: Backend) -> Result<(), String> {
+	*self.backend.lock().unwrap() = Some(backend);
+	let path = self.install_dir.join(BACKEND_FILE);
+	fs::create_dir_all(&self.install_dir).map_err(|e| format!("Cannot create directory {:?}: {e}", self.install_dir))?;
+	fs::write(&path, backend.get_name()).map_err(|e| format!("Cannot write {path:?}: {e}"))
+    }
+
+    // None when no operation is ongoing.
+    pub fn get_progress(&self) -> Option<InstallProgress> {
+	self.progress.lock().unwrap().clone()
+    }
+
+    fn set_step(&self, step: &str) {
+	println!("{step}");
+	*self.progress.lock().unwrap() = Some(InstallProgress { step: step.to_string(), download: None });
+    }
+
+    fn set_download_progress(&self, download: &DownloadProgress) {
+	self.progress.lock().unwrap().get_or_insert_with(InstallProgress::default).download = Some(download.clone());
+    }
+
+    fn get_path_info(&self) -> Result<PathInfo, String> {
+	match self.get_backend() {
+	    Some(backend) => {
+		let mut pi = PathInfo::new(&self.install_dir, backend, &self.get_version());
+		if let Some(record) = &*self.external.lock().unwrap() {
+		    pi.set_external(record, &self.networks);
+		}
+		Ok(pi)
+	    },
 	    None => Err("KataGo has not been initialized with path information!".to_string()),
 	}
     }
 
-    pub fn make_analysis_engine(&self) -> Result<ChildProcessEngine, String> {
+    // Network is the file name of a network of the network manager,
+    // None for the default analysis network.
+    pub fn make_analysis_engine(&self, network: Option<&str>) -> Result<ChildProcessEngine, String> {
+	self.make_engine(ConfigKind::Analysis, network)
+    }
+
+    // The human model is added when the config has a human profile.
+    pub fn make_human_engine(&self, network: Option<&str>) -> Result<ChildProcessEngine, String> {
+	self.make_engine(ConfigKind::Human, network)
+    }
+
+    fn make_engine(&self, kind: ConfigKind, network: Option<&str>) -> Result<ChildProcessEngine, String> {
 	let pi = self.get_path_info()?;
-	let exe = &pi.katago_exe;
-	let exe = exe.to_str().ok_or(format!("Cannot convert path to string: {exe:?}"))?;
-	let model = &pi.analysis_model;
-	let model = model.to_str().ok_or(format!("Cannot convert path to string: {model:?}"))?;
-	ChildProcessEngine::new(&format!("{exe} gtp -model {model}"))
+	let model = match network {
+	    Some(name) => self.networks.get_path(name),
+	    None => pi.analysis_model.clone(),
+	};
+	if !model.exists() {
+	    return Err(format!("Network {model:?} is not installed!"));
+	}
+	let config = self.load_config(kind)?;
+	let human_model = Some(&pi.human_model)
+	    .filter(|x| x.exists() && config.get("humanSLProfile").is_some_and(|x| !x.is_empty()));
+	let spec = pi.gtp_launch_spec(&model, &self.get_config_path(kind), human_model.map(|x| x.as_path()))?;
+	ChildProcessEngine::from_spec(spec).map_err(|e| e.to_string())
+    }
+
+    pub fn get_config_path(&self, kind: ConfigKind) -> PathBuf {
+	self.install_dir.join(CONFIGS_DIR).join(kind.get_file_name())
+    }
+
+    // Generates the config if it is missing.
+    pub fn load_config(&self, kind: ConfigKind) -> Result<KataGoConfig, String> {
+	let path = self.get_config_path(kind);
+	if path.exists() {
+	    return KataGoConfig::load(&path);
+	}
+	let config = self.generate_config(kind)?;
+	config.save(&path)?;
+	println!("Generated KataGo config {path:?}.");
+	Ok(config)
     }
 
-    pub fn make_human_engine(&self) -> Result<ChildProcessEngine, String> {
-	// TODO: Human engine
-	// For now using the analysis engine as human engine
-	self.make_analysis_engine()
+    pub fn save_config(&self, kind: ConfigKind, config: &KataGoConfig) -> Result<(), String> {
+	config.save(&self.get_config_path(kind))
+    }
+
+    // Starts from the example config of the installed release, which
+    // documents every setting, or from the built-in template.
+    fn generate_config(&self, kind: ConfigKind) -> Result<KataGoConfig, String> {
+	let example = self.get_path_info().ok().map(|pi| pi.katago_dir.join("default_gtp.cfg"));
+	let mut config = match example.filter(|x| x.exists()) {
+	    Some(path) => KataGoConfig::load(&path)?,
+	    None => KataGoConfig::template(),
+	};
+	// KataGo takes a relative log directory from its working
+	// directory, which is the directory of the app.
+	let logs = self.install_dir.join(LOGS_DIR);
+	config.set("logDir", &logs.to_string_lossy());
+	for (key, value) in kind.get_defaults() {
+	    config.set(key, value);
+	}
+	Ok(config)
     }
 
     // fn try_lock(&self) -> Result<MutexGuard<'_, ()>, String> {
@@ -121,7 +514,8 @@ impl KataGoInstaller {
     fn is_installed_without_lock(&self) -> bool {
 	match self.get_path_info() {
 	    Ok(path_info) => {
-		path_info.katago_exe.exists()
+		// External installs need no human model.
+		path_info.katago_exe.exists() && path_info.analysis_model.exists() && (path_info.external || path_info.human_model.exists())
 	    },
 	    Err(s) => {
 		println!("Returning false from is_installed, path_info not setup. {s}");
@@ -130,16 +524,93 @@ impl KataGoInstaller {
 	}
     }
 
-    pub fn install(&self, kill_signal_rx: Receiver<()>) -> Result<(), String> {
+    // Installs the detected backends in order of preference until one
+    // passes the test, and selects it.
+    pub fn install_auto(&self, kill_signal_rx: Receiver<()>) -> Result<Backend, String> {
+	let mut errors = Vec::new();
+	for backend in Backend::detect() {
+	    println!("Trying KataGo backend {}...", backend.get_name());
+	    self.set_backend(backend)?;
+	    let r = self.install(&kill_signal_rx).and_then(|()| self.test(&kill_signal_rx));
+	    match r {
+		Ok(version) => {
+		    println!("KataGo backend {} works, version {version}.", backend.get_name());
+		    return Ok(backend);
+		},
+		Err(s) if s == CANCELLED => { return Err(s); },
+		Err(s) => {
+		    println!("KataGo backend {} doesn't work: {s}", backend.get_name());
+		    errors.push(format!("{}: {s}", backend.get_name()));
+		},
+	    }
+	}
+	Err(format!("No KataGo backend works! {}", errors.join("; ")))
+    }
+
+    // Every step stops with CANCELLED when the kill signal is received.
+    pub fn install(&self, kill_signal_rx: &Receiver<()>) -> Result<(), String> {
 	let _guard = self.lock();
+	let r = self.install_steps(&HashMap::new(), kill_signal_rx);
+	*self.progress.lock().unwrap() = None;
+	r
+    }
 
+    // Installs from local files instead of downloading, for machines
+    // without internet access. Sources are the KataGo archive and model
+    // files of the release, or directories containing them. Files that
+    // are already installed need not be given.
+    pub fn install_offline(&self, sources: &[PathBuf], kill_signal_rx: &Receiver<()>) -> Result<(), String> {
+	let _guard = self.lock();
+	let pi = self.get_path_info()?;
+	let urls = find_local_artifacts(&pi, sources)?;
+	let r = self.install_steps(&urls, kill_signal_rx);
+	*self.progress.lock().unwrap() = None;
+	r
+    }
+
+    // Installing switches from an external KataGo to the one of the
+    // app, unless it fails.
+    fn install_steps(&self, urls: &HashMap<PathBuf, String>, kill_signal_rx: &Receiver<()>) -> Result<(), String> {
+	let external = self.external.lock().unwrap().take();
+	let version = self.get_version();
+	if external.is_some() {
+	    *self.version.lock().unwrap() = KATAGO_VERSION.to_string();
+	}
+	let r = self.install_app_steps(urls, kill_signal_rx);
+	if r.is_err() && external.is_some() {
+	    *self.external.lock().unwrap() = external;
+	    *self.version.lock().unwrap() = version;
+	}
+	r
+    }
+
+    // urls replace the URLs of the artifacts, keyed by their paths.
+    fn install_app_steps(&self, urls: &HashMap<PathBuf, String>, kill_signal_rx: &Receiver<()>) -> Result<(), String> {
 	println!("Installing KataGo archieve...");
 
 	let dir = &self.get_path_info()?.katago_dir;
 	println!("Installation directory: {:?}", dir);
+	recover_swap_in(dir).map_err(|e| format!("Cannot recover interrupted install in {dir:?}: {e}"))?;
 	
-	self.download_all().map_err(|e| e.to_string())?;
-	self.extract().map_err(|e| e.to_string())?;
+	self.download_all(urls, kill_signal_rx).map_err(|e| e.to_string())?;
+	self.extract(kill_signal_rx).map_err(|e| e.to_string())?;
+	// The previous version is kept until the new one works.
+	match self.check_extracted(kill_signal_rx) {
+	    Ok(()) => {
+		let previous = previous_path(dir);
+		if previous.exists() {
+		    fs::remove_dir_all(&previous).map_err(|e| format!("Cannot remove {previous:?}: {e}"))?;
+		}
+		self.record_install()
+	    },
+	    Err(s) => match self.rollback()? {
+		true => Err(format!("{s} Restored the previous version.")),
+		false => Err(s),
+	    },
+	}
+    }
+
+    fn check_extracted(&self, kill_signal_rx: &Receiver<()>) -> Result<(), String> {
 	if !self.is_installed_without_lock() {
 	    return Err("Extraction unsuccessful!".to_string());
 	}
@@ -147,21 +618,49 @@ impl KataGoInstaller {
 	if !self.is_tuned_without_lock() {
 	    println!("Tuning unsuccessful!");
 	}
-
+	for kind in ConfigKind::ALL {
+	    self.load_config(kind)?;
+	}
+	// Only an update can be rolled back, a first install is tested
+	// by the caller.
+	if previous_path(&self.get_path_info()?.katago_dir).exists() {
+	    self.set_step("Testing KataGo...");
+	    self.test_without_lock(kill_signal_rx)?;
+	}
 	Ok(())
     }
 
-    fn tune(&self, kill_signal_rx: Receiver<()>) -> Result<(), String> {
-	let pi = &self.get_path_info()?;
-	let exe = &pi.katago_exe;
-	let exe = exe.to_str().ok_or(format!("Cannot convert path to string: {exe:?}"))?;
-	let model = &pi.analysis_model;
-	let model = model.to_str().ok_or(format!("Cannot convert path to string: {model:?}"))?;
+    // Puts the previous version back in place of the extracted one.
+    // Returns false if there is no previous version.
+    fn rollback(&self) -> Result<bool, String> {
+	let dir = self.get_path_info()?.katago_dir;
+	let previous = previous_path(&dir);
+	if !previous.exists() {
+	    return Ok(false);
+	}
+	println!("Restoring previous KataGo from {previous:?}...");
+	if dir.exists() {
+	    fs::remove_dir_all(&dir).map_err(|e| format!("Cannot remove {dir:?}: {e}"))?;
+	}
+	fs::rename(&previous, &dir).map_err(|e| format!("Cannot restore {previous:?}: {e}"))?;
+	Ok(true)
+    }
 
-	let command = format!("{exe} benchmark -model {model}");
-	println!("Running command: {command:?}");
+    fn tune(&self, kill_signal_rx: &Receiver<()>) -> Result<(), String> {
+	let pi = &self.get_path_info()?;
+	if pi.external {
+	    println!("External KataGo tunes itself on the first start.");
+	    return Ok(());
+	}
+	if !pi.backend.needs_tuning() {
+	    println!("KataGo backend {} needs no tuning.", pi.backend.get_name());
+	    return Ok(());
+	}
+	self.set_step("Tuning KataGo for the GPU...");
+	let spec = LaunchSpec::from_argv(&[pi.katago_exe.as_os_str(), "benchmark".as_ref(), "-model".as_ref(), pi.analysis_model.as_os_str()])?;
+	println!("Running command: {}", spec.to_command_str());
 
-	let mut child = SmartChild::from_command_str(&command)?;
+	let mut child = SmartChild::spawn(&spec)?;
 	
 	// Reading blocks. Find a way to read non-blocking.
 	// let reader = io::BufReader::new(child.take_stdout_and_stderr()?);
@@ -177,12 +676,12 @@ impl KataGoInstaller {
 		println!("Benchmark process exited with status: {}", status);
 		break;
 	    }
-	    if let Ok(()) = kill_signal_rx.try_recv() {
+	    if let Err(s) = check_cancelled(kill_signal_rx) {
 		println!("Kill signal received before the benchmark process exited. Killing.");
 		drop(child);
-		break;
+		return Err(s);
 	    }
-	    thread::sleep(std::time::Duration::from_millis(200));
+	    thread::sleep(Duration::from_millis(200));
 	}
 
 	Ok(())
@@ -196,7 +695,7 @@ impl KataGoInstaller {
     fn is_tuned_without_lock(&self) -> bool {
 	match self.get_path_info() {
 	    Ok(path_info) => {
-		path_info.katago_dir.join("KataGoData").join("opencltuning").exists()
+		path_info.external || !path_info.backend.needs_tuning() || path_info.katago_dir.join("KataGoData").join("opencltuning").exists()
 	    },
 	    Err(s) => {
 		println!("Returning false from is_installed, path_info not setup. {s}");
@@ -205,93 +704,1111 @@ impl KataGoInstaller {
 	}
     }
 
-    pub fn test(&self) -> Result<String, String> {
+    pub fn test(&self, kill_signal_rx: &Receiver<()>) -> Result<String, String> {
 	let _guard = self.lock();
-	self.test_without_lock()
+	self.set_step("Testing KataGo...");
+	let r = self.test_without_lock(kill_signal_rx);
+	*self.progress.lock().unwrap() = None;
+	r
     }
 
-    fn test_without_lock(&self) -> Result<String, String> {
-	let pi = &self.get_path_info()?;
-	let exe = &pi.katago_exe;
-	let exe = exe.to_str().ok_or(format!("Cannot convert path to string: {exe:?}"))?;
-	let model = &pi.analysis_model;
-	let model = model.to_str().ok_or(format!("Cannot convert path to string: {model:?}"))?;
-
-	let command = format!("{exe} gtp -model {model}");
-	let mut engine = ChildProcessEngine::new(&command)?;
-	let version = engine.version();
+    fn test_without_lock(&self, kill_signal_rx: &Receiver<()>) -> Result<String, String> {
+	let mut engine = self.make_analysis_engine(None)?;
+	// The first command waits for the model to load, which includes
+	// OpenCL tuning if it hasn't been done yet. A backend without a
+	// usable device fails here. Waiting in short timeouts to notice
+	// the kill signal.
+	let id = engine.write_command("version")?;
+	engine.set_command_timeout(Some(Duration::from_millis(200)));
+	let start = Instant::now();
+	let version = loop {
+	    match engine.read_response(id) {
+		Err(GtpError::Timeout(_)) if start.elapsed() < TEST_TIMEOUT => {
+		    check_cancelled(kill_signal_rx)?;
+		},
+		r => { break r; },
+	    }
+	};
 	engine.quit()?;
-	version
+	version.map_err(|e| e.to_string())
     }
 
-    fn download_all(&self) -> Result<(), Box<dyn Error>> {
+    fn download_all(&self, urls: &HashMap<PathBuf, String>, kill_signal_rx: &Receiver<()>) -> Result<(), Box<dyn Error>> {
 	let pi = self.get_path_info()?;
 	let dir = &pi.katago_dir;
 	let install_dir = pi.katago_dir.parent().ok_or(format!("{dir:?} does not have a parent! It appears to be the root directory. Not proceeding with download."))?;
 
 	// Create installation directory
-	fs::create_dir_all(&install_dir)?;
+	fs::create_dir_all(install_dir)?;
 
-	// Download KataGo
-	download(&pi.katago_url, &pi.katago_zip)?;
+	fs::create_dir_all(self.networks.get_dir())?;
 
-	// Download models
-	download(&pi.analysis_model_url, &pi.analysis_model)?;
-	download(&pi.human_model_url, &pi.human_model)?;
+	for (name, url, path, sha256) in pi.get_artifacts() {
+	    if sha256.is_none() {
+		println!("No pinned SHA-256 for {path:?}, checking its integrity instead.");
+	    }
+	    self.set_step(&format!("Downloading {name}..."));
+	    let url = urls.get(path).map_or(url, |x| x.as_str());
+	    download(url, path, sha256, kill_signal_rx, &mut |p| self.set_download_progress(p))?;
+	}
 	
 	Ok(())
     }
 
-    fn extract(&self) -> Result<(), Box<dyn Error>> {
+    // Checks the downloaded files against their digests and deletes the
+    // corrupt ones, so that the next installation downloads them
+    // again. Returns the deleted files.
+    pub fn verify(&self, kill_signal_rx: &Receiver<()>) -> Result<Vec<PathBuf>, String> {
+	let _guard = self.lock();
+	self.set_step("Verifying downloaded files...");
+	let r = self.verify_without_lock(kill_signal_rx);
+	*self.progress.lock().unwrap() = None;
+	r
+    }
+
+    fn verify_without_lock(&self, kill_signal_rx: &Receiver<()>) -> Result<Vec<PathBuf>, String> {
+	let pi = self.get_path_info()?;
+	let mut deleted = Vec::new();
+	for (_name, _url, path, sha256) in pi.get_artifacts() {
+	    if path.exists() && !verify_file(path, sha256, kill_signal_rx).map_err(|e| e.to_string())? {
+		println!("{path:?} is corrupt. Deleting.");
+		remove_download(path).map_err(|e| e.to_string())?;
+		deleted.push(path.clone());
+	    }
+	}
+	Ok(deleted)
+    }
+
+    // Extracts into a staging directory and swaps it in. The installed
+    // version stays untouched if the archive is broken or the
+    // extraction is cancelled.
+    fn extract(&self, kill_signal_rx: &Receiver<()>) -> Result<(), Box<dyn Error>> {
+	self.set_step("Extracting KataGo...");
 	let path_info = self.get_path_info()?;
 	let zip = &path_info.katago_zip;
 	let dir = &path_info.katago_dir;
+	let staging = staging_path(dir);
 
 	println!("Extracting '{zip:?}' to '{dir:?}'...");
 	
 	if !zip.exists() {
 	    return Err(format!("Zip file does not exist: {zip:?}").into());
 	}
+	if staging.exists() {
+	    fs::remove_dir_all(&staging)?;
+	}
 
-	let file = fs::File::open(zip)?;
-	let mut archive = ZipArchive::new(file)?;
+	let r = extract_archive(zip, &staging, kill_signal_rx).and_then(|()| {
+	    let exe = staging.join(path_info.katago_exe.file_name().unwrap());
+	    if !exe.is_file() {
+		return Err(format!("{zip:?} doesn't contain the KataGo executable {:?}!", exe.file_name().unwrap()).into());
+	    }
+	    make_executable(&exe)?;
+	    Ok(())
+	});
+	if let Err(e) = r {
+	    let _ = fs::remove_dir_all(&staging);
+	    return Err(e);
+	}
+	swap_in(&staging, dir)?;
+	Ok(())
+    }
+}
 
-	if dir.exists() {
-	    println!("{dir:?} exists. Deleting...");
-	    fs::remove_dir_all(dir)?;
+
+impl Backend {
+    pub const ALL: [Backend; 5] = [Backend::EigenAvx2, Backend::Eigen, Backend::OpenCL, Backend::Cuda, Backend::TensorRT];
+
+    pub fn get_name(&self) -> &'static str {
+	match self {
+	    Backend::EigenAvx2 => "eigenavx2",
+	    Backend::Eigen => "eigen",
+	    Backend::OpenCL => "opencl",
+	    Backend::Cuda => "cuda",
+	    Backend::TensorRT => "tensorrt",
+	}
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+	Self::ALL.into_iter().find(|x| x.get_name() == name)
+    }
+
+    pub fn get_description(&self) -> &'static str {
+	match self {
+	    Backend::EigenAvx2 => "CPU (Eigen, AVX2)",
+	    Backend::Eigen => "CPU (Eigen)",
+	    Backend::OpenCL => "GPU (OpenCL)",
+	    Backend::Cuda => "NVIDIA GPU (CUDA)",
+	    Backend::TensorRT => "NVIDIA GPU (TensorRT)",
+	}
+    }
+
+    // Only OpenCL tunes itself for the device.
+    pub fn needs_tuning(&self) -> bool {
+	*self == Backend::OpenCL
+    }
+
+    // Backends that may work on this machine, most preferred first.
+    // GPU backends are guesses, it takes a test to tell whether they
+    // work. The plain Eigen backend works everywhere.
+    pub fn detect() -> Vec<Self> {
+	let mut backends = Vec::new();
+	if has_nvidia_gpu() {
+	    backends.push(Backend::TensorRT);
+	    backends.push(Backend::Cuda);
+	}
+	if has_opencl() {
+	    backends.push(Backend::OpenCL);
+	}
+	if has_avx2() {
+	    backends.push(Backend::EigenAvx2);
+	}
+	backends.push(Backend::Eigen);
+	backends
+    }
+
+    // Name of the release archive without extension.
+    fn archive_name(&self, os: &str, version: &str) -> String {
+	let build = match self {
+	    Backend::EigenAvx2 => "eigenavx2",
+	    Backend::Eigen => "eigen",
+	    Backend::OpenCL => "opencl",
+	    Backend::Cuda => "cuda12.1-cudnn8.9.7",
+	    Backend::TensorRT => "trt10.2.0-cuda12.5",
+	};
+	format!("katago-{version}-{build}-{os}-x64")
+    }
+}
+
+
+// Files named katago that can be executed, in the order of the
+// directories and without duplicates.
+fn find_katago(dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let name = format!("katago{}", std::env::consts::EXE_SUFFIX);
+    let mut found: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+	let Ok(path) = fs::canonicalize(dir.join(&name)) else { continue; };
+	if path.is_file() && is_executable(&path) && !found.contains(&path) {
+	    found.push(path);
 	}
+    }
+    found
+}
+
+
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+	use std::os::unix::fs::PermissionsExt;
+	fs::metadata(path).is_ok_and(|x| x.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+	path.is_file()
+    }
+}
+
+
+fn has_nvidia_gpu() -> bool {
+    process::Command::new("nvidia-smi").arg("-L")
+	.stdout(process::Stdio::null())
+	.stderr(process::Stdio::null())
+	.status().is_ok_and(|x| x.success())
+}
 
-	for i in 0..archive.len() {
-            let mut file = archive.by_index(i)?;
-            let out_path = Path::new(dir).join(file.name());
 
-	    println!("{:?}", out_path);
-            if file.is_dir() {
-		fs::create_dir_all(&out_path)?;
-            } else {
-		if let Some(parent) = out_path.parent() {
-                    fs::create_dir_all(parent)?;
+fn has_opencl() -> bool {
+    if cfg!(windows) {
+	Path::new("C:\\Windows\\System32\\OpenCL.dll").exists()
+    } else {
+	// Installable client drivers of the OpenCL vendors.
+	fs::read_dir("/etc/OpenCL/vendors").is_ok_and(|mut x| x.next().is_some())
+    }
+}
+
+
+fn has_avx2() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+	is_x86_feature_detected!("avx2")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+	false
+    }
+}
+
+
+impl PathInfo {
+    fn new(install_dir: &Path, backend: Backend, version: &str) -> Self {
+	let os = if cfg!(target_os = "windows") { "windows" } else { "linux" };
+	let name = backend.archive_name(os, version);
+	let katago_url = format!("https://github.com/lightvector/KataGo/releases/download/{version}/{name}.zip");
+
+	let analysis_model_url = "https://github.com/lightvector/KataGo/releases/download/v1.4.5/g170e-b20c256x2-s5303129600-d1228401921.bin.gz";
+	let analysis_model = "g170e-b20c256x2-s5303129600-d1228401921.bin.gz";
+	let human_model_url = "https://github.com/lightvector/KataGo/releases/download/v1.15.0/b18c384nbt-humanv0.bin.gz";
+	let human_model = "b18c384nbt-humanv0.bin.gz";
+
+	let networks = install_dir.join(NETWORKS_DIR);
+	let dir = install_dir.join(&name);
+	let mut exe = String::from("katago");
+	if cfg!(windows) {
+	    exe.push_str(".exe");
+	}
+
+	let zip = format!("{name}.zip");
+	PathInfo {
+	    backend: backend,
+	    katago_url: katago_url,
+	    katago_sha256: pinned_sha256(&zip),
+	    katago_zip: install_dir.join(zip),
+	    katago_exe: dir.join(exe),
+	    katago_dir: dir,
+	    analysis_model_url: analysis_model_url.to_string(),
+	    analysis_model: networks.join(analysis_model),
+	    analysis_model_sha256: pinned_sha256(analysis_model),
+	    human_model_url: human_model_url.to_string(),
+	    human_model: networks.join(human_model),
+	    human_model_sha256: pinned_sha256(human_model),
+	    external: false,
+	}
+    }
+
+    // Points the executable and the default networks to the external
+    // install. Downloads and extraction still use the app's paths.
+    fn set_external(&mut self, record: &InstallRecord, networks: &NetworkManager) {
+	if let Some(exe) = &record.exe {
+	    self.katago_exe = exe.clone();
+	}
+	let (human, analysis): (Vec<_>, Vec<_>) = record.models.iter().partition(|x| x.contains("human"));
+	if let Some(model) = analysis.first() {
+	    self.analysis_model = networks.get_path(model);
+	}
+	if let Some(model) = human.first() {
+	    self.human_model = networks.get_path(model);
+	}
+	self.external = true;
+    }
+
+    // Name, URL, path and pinned digest of the files to download.
+    fn get_artifacts(&self) -> [(&str, &str, &PathBuf, Option<&str>); 3] {
+	[
+	    ("KataGo", &self.katago_url, &self.katago_zip, self.katago_sha256.as_deref()),
+	    ("analysis model", &self.analysis_model_url, &self.analysis_model, self.analysis_model_sha256.as_deref()),
+	    ("human model", &self.human_model_url, &self.human_model, self.human_model_sha256.as_deref()),
+	]
+    }
+
+    // Paths are passed as separate arguments, so spaces in the install
+    // directory need no quoting.
+    fn gtp_launch_spec(&self, model: &Path, config: &Path, human_model: Option<&Path>) -> Result<LaunchSpec, String> {
+	let mut argv = vec![self.katago_exe.as_os_str(), "gtp".as_ref(), "-model".as_ref(), model.as_os_str(), "-config".as_ref(), config.as_os_str()];
+	if let Some(human_model) = human_model {
+	    argv.extend(["-human-model".as_ref(), human_model.as_os_str()]);
+	}
+	LaunchSpec::from_argv(&argv)
+    }
+}
+
+
+impl DownloadProgress {
+    pub fn get_fraction(&self) -> Option<f32> {
+	self.total.filter(|x| *x > 0).map(|total| self.done as f32 / total as f32)
+    }
+
+    // Estimated time left at the current rate.
+    pub fn get_eta(&self) -> Option<Duration> {
+	let total = self.total?;
+	if self.rate <= 0.0 {
+	    return None;
+	}
+	Some(Duration::from_secs_f64(total.saturating_sub(self.done) as f64 / self.rate))
+    }
+}
+
+
+// Stops an installer step when the kill signal is received or its
+// sender is gone.
+fn check_cancelled(kill_signal_rx: &Receiver<()>) -> Result<(), String> {
+    match kill_signal_rx.try_recv() {
+	Err(TryRecvError::Empty) => Ok(()),
+	Ok(()) | Err(TryRecvError::Disconnected) => Err(CANCELLED.to_string()),
+    }
+}
+
+
+// Matches the source files to the artifacts by file name. A single
+// zip among the sources is taken as the KataGo archive whatever its
+// name. Validates the files and returns "file://" URLs keyed by
+// artifact path.
+fn find_local_artifacts(pi: &PathInfo, sources: &[PathBuf]) -> Result<HashMap<PathBuf, String>, String> {
+    let mut files = Vec::new();
+    for source in sources {
+	if source.is_dir() {
+	    let entries = fs::read_dir(source).map_err(|e| format!("Cannot read directory {source:?}: {e}"))?;
+	    files.extend(entries.filter_map(|x| x.ok()).map(|x| x.path()).filter(|x| x.is_file()));
+	} else if source.is_file() {
+	    files.push(source.clone());
+	} else {
+	    return Err(format!("{source:?} doesn't exist!"));
+	}
+    }
+
+    let zips: Vec<_> = files.iter().filter(|x| x.extension().is_some_and(|x| x == "zip")).collect();
+    let mut urls = HashMap::new();
+    let mut missing = Vec::new();
+    for (name, _url, path, _sha256) in pi.get_artifacts() {
+	let file = files.iter().find(|x| x.file_name() == path.file_name())
+	    .or(if *path == pi.katago_zip && zips.len() == 1 { Some(zips[0]) } else { None });
+	match file {
+	    Some(file) => {
+		if *path == pi.katago_zip {
+		    validate_katago_zip(file)?;
+		} else {
+		    validate_model(file)?;
 		}
-		let mut out_file = fs::File::create(&out_path)?;
-		io::copy(&mut file, &mut out_file)?;
-            }
+		let file = fs::canonicalize(file).map_err(|e| format!("Cannot resolve {file:?}: {e}"))?;
+		urls.insert(path.clone(), format!("file://{}", file.display()));
+	    },
+	    None if path.exists() => {},
+	    None => { missing.push(format!("{name} ({})", path.file_name().unwrap_or_default().to_string_lossy())); },
 	}
+    }
+    if !missing.is_empty() {
+	return Err(format!("Local files are missing: {}", missing.join(", ")));
+    }
+    Ok(urls)
+}
 
-	Ok(())
+
+fn validate_katago_zip(path: &Path) -> Result<(), String> {
+    let file = fs::File::open(path).map_err(|e| format!("Cannot open {path:?}: {e}"))?;
+    let archive = ZipArchive::new(file).map_err(|e| format!("{path:?} is not a zip archive: {e}"))?;
+    let has_exe = archive.file_names().any(|x| matches!(Path::new(x).file_name().and_then(|x| x.to_str()), Some("katago" | "katago.exe")));
+    if !has_exe {
+	return Err(format!("{path:?} doesn't contain a KataGo executable!"));
     }
+    Ok(())
 }
 
 
-fn download(url: &str, file_path: &Path) -> Result<bool, Box<dyn Error>> {
-    // Return true if file doesn't exist and download is successful.
-    // Return false if file exists.
+// Networks are gzip files.
+fn validate_model(path: &Path) -> Result<(), String> {
+    let mut magic = [0; 2];
+    fs::File::open(path).and_then(|mut x| x.read_exact(&mut magic)).map_err(|e| format!("Cannot read {path:?}: {e}"))?;
+    if magic != [0x1f, 0x8b] {
+	return Err(format!("{path:?} is not a gzip compressed network!"));
+    }
+    Ok(())
+}
+
+
+fn pinned_sha256(file_name: &str) -> Option<String> {
+    SHA256_DIGESTS.iter().find(|(name, _)| *name == file_name).map(|(_, digest)| digest.to_string())
+}
+
+
+// Downloads the file unless it exists with the expected digest, which
+// is the pinned one or else the one recorded after the download.
+// Partial downloads are resumed. A file that doesn't match the pinned
+// digest, or without one fails the integrity check, is downloaded once
+// more from scratch. Returns true if the file is downloaded, false if
+// it exists.
+fn download(url: &str, file_path: &Path, sha256: Option<&str>, kill_signal_rx: &Receiver<()>, progress: &mut dyn FnMut(&DownloadProgress)) -> Result<bool, Box<dyn Error>> {
     if file_path.exists() {
-	println!("{file_path:?} already exists. Not downloading again.");
-	Ok(false)
-    } else {
+	if verify_file(file_path, sha256, kill_signal_rx)? {
+	    println!("{file_path:?} already exists. Not downloading again.");
+	    return Ok(false);
+	}
+	println!("{file_path:?} is corrupt. Downloading again.");
+	remove_download(file_path)?;
+    }
+
+    let part_path = part_path(file_path);
+    for attempt in 1..=2 {
 	println!("Downloading from '{url}' to '{file_path:?}'...");
-	let content = reqwest::blocking::get(url)?.bytes()?;
-	fs::write(file_path, content)?;
-	Ok(true)
+	fetch(url, &part_path, kill_signal_rx, progress)?;
+	let digest = file_sha256(&part_path, kill_signal_rx)?;
+	let problem = match sha256 {
+	    Some(expected) if expected != digest => Some(format!("SHA-256 of {url} is {digest}, expected {expected}!")),
+	    Some(_) => None,
+	    None => check_integrity(&part_path, kill_signal_rx)?,
+	};
+	match problem {
+	    Some(s) => {
+		fs::remove_file(&part_path)?;
+		if attempt == 2 {
+		    return Err(s.into());
+		}
+		println!("{s} Downloading from scratch.");
+	    },
+	    None => {
+		fs::rename(&part_path, file_path)?;
+		fs::write(digest_path(file_path), &digest)?;
+		break;
+	    },
+	}
+    }
+    Ok(true)
+}
+
+
+// Streams the URL to the ".part" file, appending to it if it exists
+// and the source can resume. Besides HTTP(S), URLs can be "file://"
+// followed by a local path.
+fn fetch(url: &str, part_path: &Path, kill_signal_rx: &Receiver<()>, progress: &mut dyn FnMut(&DownloadProgress)) -> Result<(), Box<dyn Error>> {
+    let offset = fs::metadata(part_path).map_or(0, |x| x.len());
+    let (mut response, total, resumed) = match url.strip_prefix("file://") {
+	Some(path) => open_local(Path::new(path), offset)?,
+	None => open_http(url, part_path, offset)?,
+    };
+    let (mut file, offset) = if resumed {
+	println!("Resuming download at byte {offset}.");
+	(fs::OpenOptions::new().append(true).open(part_path)?, offset)
+    } else {
+	(fs::File::create(part_path)?, 0)
+    };
+
+    let start = Instant::now();
+    let mut last_report = start;
+    let mut done = offset;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+	check_cancelled(kill_signal_rx)?;
+	let n = response.read(&mut buf)?;
+	if n > 0 {
+	    file.write_all(&buf[..n])?;
+	    done += n as u64;
+	}
+	if n == 0 || last_report.elapsed() >= PROGRESS_INTERVAL {
+	    last_report = Instant::now();
+	    let rate = (done - offset) as f64 / start.elapsed().as_secs_f64().max(0.001);
+	    progress(&DownloadProgress { done: done, total: total, rate: rate });
+	}
+	if n == 0 {
+	    break;
+	}
+    }
+    file.flush()?;
+
+    if total.is_some_and(|x| x != done) {
+	return Err(format!("Download of {url} is incomplete: {done} of {} bytes.", total.unwrap()).into());
+    }
+    Ok(())
+}
+
+
+// Body of a download, its total size and whether the body starts at
+// the offset of the partial file.
+type Source = (Box<dyn Read>, Option<u64>, bool);
+
+
+fn open_http(url: &str, part_path: &Path, offset: u64) -> Result<Source, Box<dyn Error>> {
+    let mut request = reqwest::blocking::Client::new().get(url);
+    if offset > 0 {
+	request = request.header(reqwest::header::RANGE, format!("bytes={offset}-"));
+    }
+    let response = request.send()?;
+    if response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+	// The partial file is not a prefix of the file.
+	fs::remove_file(part_path)?;
+	return open_http(url, part_path, 0);
+    }
+    let response = response.error_for_status()?;
+
+    // Servers without range support send the whole file.
+    let resumed = offset > 0 && response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+    let total = response.content_length().map(|x| if resumed { x + offset } else { x });
+    Ok((Box::new(response), total, resumed))
+}
+
+
+fn open_local(path: &Path, offset: u64) -> Result<Source, Box<dyn Error>> {
+    let mut file = fs::File::open(path).map_err(|e| format!("Cannot open {path:?}: {e}"))?;
+    let total = file.metadata()?.len();
+    let resumed = offset > 0 && offset <= total;
+    if resumed {
+	file.seek(SeekFrom::Start(offset))?;
+    }
+    Ok((Box::new(file), Some(total), resumed))
+}
+
+
+// A file without a pinned or recorded digest, e.g. one downloaded
+// before digests were recorded, is checked for integrity. Its digest
+// is recorded if it passes, so that the check runs once.
+fn verify_file(path: &Path, sha256: Option<&str>, kill_signal_rx: &Receiver<()>) -> Result<bool, Box<dyn Error>> {
+    let expected = match sha256 {
+	Some(s) => s.to_string(),
+	None => match fs::read_to_string(digest_path(path)) {
+	    Ok(s) => s.trim().to_string(),
+	    Err(_) => {
+		if let Some(s) = check_integrity(path, kill_signal_rx)? {
+		    println!("{s}");
+		    return Ok(false);
+		}
+		fs::write(digest_path(path), file_sha256(path, kill_signal_rx)?)?;
+		return Ok(true);
+	    },
+	},
+    };
+    Ok(file_sha256(path, kill_signal_rx)? == expected)
+}
+
+
+// Reads every entry of a zip archive or the whole stream of a gzip
+// file, which checks their CRC-32, and returns what is wrong with the
+// file. Catches truncated and damaged downloads, not tampered ones.
+// Files of other types pass.
+fn check_integrity(path: &Path, kill_signal_rx: &Receiver<()>) -> Result<Option<String>, Box<dyn Error>> {
+    let name = path.to_string_lossy();
+    let name = name.strip_suffix(".part").unwrap_or(&name);
+    let r = if name.ends_with(".zip") {
+	match ZipArchive::new(fs::File::open(path)?) {
+	    Ok(mut archive) => (0..archive.len()).try_for_each(|i| {
+		let entry = archive.by_index(i).map_err(io::Error::other)?;
+		read_all(entry, kill_signal_rx)
+	    }),
+	    Err(e) => Err(io::Error::other(e)),
+	}
+    } else if name.ends_with(".gz") {
+	read_all(GzDecoder::new(fs::File::open(path)?), kill_signal_rx)
+    } else {
+	Ok(())
+    };
+    match r {
+	Ok(()) => Ok(None),
+	Err(e) if e.to_string() == CANCELLED => Err(CANCELLED.into()),
+	Err(e) => Ok(Some(format!("{path:?} is damaged: {e}"))),
+    }
+}
+
+
+fn read_all<R: Read>(mut reader: R, kill_signal_rx: &Receiver<()>) -> io::Result<()> {
+    let mut buf = vec![0; 1024 * 1024];
+    loop {
+	check_cancelled(kill_signal_rx).map_err(io::Error::other)?;
+	if reader.read(&mut buf)? == 0 {
+	    return Ok(());
+	}
+    }
+}
+
+
+fn file_sha256(path: &Path, kill_signal_rx: &Receiver<()>) -> Result<String, Box<dyn Error>> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1024 * 1024];
+    loop {
+	check_cancelled(kill_signal_rx)?;
+	let n = file.read(&mut buf)?;
+	if n == 0 {
+	    break;
+	}
+	hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+
+// Entries are extracted by their enclosed names, so that names like
+// "../x" or "/x" cannot write outside the directory. Unix permissions
+// are taken from the archive.
+fn extract_archive(zip: &Path, dir: &Path, kill_signal_rx: &Receiver<()>) -> Result<(), Box<dyn Error>> {
+    let mut archive = ZipArchive::new(fs::File::open(zip)?)?;
+    fs::create_dir_all(dir)?;
+    for i in 0..archive.len() {
+	check_cancelled(kill_signal_rx)?;
+	let mut file = archive.by_index(i)?;
+	let name = file.enclosed_name().ok_or(format!("{zip:?} contains the unsafe path {:?}!", file.name()))?;
+	let out_path = dir.join(name);
+	if file.is_dir() {
+	    fs::create_dir_all(&out_path)?;
+	} else {
+	    if let Some(parent) = out_path.parent() {
+		fs::create_dir_all(parent)?;
+	    }
+	    let mut out_file = fs::File::create(&out_path)?;
+	    io::copy(&mut file, &mut out_file)?;
+	}
+	#[cfg(unix)]
+	if let Some(mode) = file.unix_mode() {
+	    use std::os::unix::fs::PermissionsExt;
+	    fs::set_permissions(&out_path, fs::Permissions::from_mode(mode & 0o777))?;
+	}
+    }
+    Ok(())
+}
+
+
+// Archives made on Windows have no permissions, the executable is
+// made executable for those who can read it.
+fn make_executable(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+	use std::os::unix::fs::PermissionsExt;
+	let mut permissions = fs::metadata(path)?.permissions();
+	let mode = permissions.mode();
+	if mode & 0o111 == 0 {
+	    permissions.set_mode(mode | (mode & 0o444) >> 2);
+	    fs::set_permissions(path, permissions)?;
+	}
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+
+// Replaces dir by staging, keeping dir as the previous version. The
+// renames are within the install directory, which makes each atomic.
+fn swap_in(staging: &Path, dir: &Path) -> io::Result<()> {
+    let previous = previous_path(dir);
+    if dir.exists() {
+	if previous.exists() {
+	    fs::remove_dir_all(&previous)?;
+	}
+	fs::rename(dir, &previous)?;
+    }
+    if let Err(e) = fs::rename(staging, dir) {
+	if previous.exists() {
+	    let _ = fs::rename(&previous, dir);
+	}
+	return Err(e);
+    }
+    Ok(())
+}
+
+
+// Cleans up after swap_in was interrupted, e.g. by a crash or power
+// loss. The previous version is put back if dir was already renamed,
+// and a leftover staging directory is removed.
+fn recover_swap_in(dir: &Path) -> io::Result<()> {
+    let previous = previous_path(dir);
+    if previous.exists() && !dir.exists() {
+	println!("Restoring previous KataGo from {previous:?}...");
+	fs::rename(&previous, dir)?;
+    }
+    let staging = staging_path(dir);
+    if staging.exists() {
+	fs::remove_dir_all(&staging)?;
+    }
+    Ok(())
+}
+
+
+fn remove_download(path: &Path) -> io::Result<()> {
+    fs::remove_file(path)?;
+    match fs::remove_file(digest_path(path)) {
+	Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+	_ => Ok(()),
+    }
+}
+
+
+fn digest_path(file_path: &Path) -> PathBuf {
+    let mut s = file_path.as_os_str().to_os_string();
+    s.push(".sha256");
+    PathBuf::from(s)
+}
+
+
+fn part_path(file_path: &Path) -> PathBuf {
+    let mut s = file_path.as_os_str().to_os_string();
+    s.push(".part");
+    PathBuf::from(s)
+}
+
+
+fn staging_path(dir: &Path) -> PathBuf {
+    let mut s = dir.as_os_str().to_os_string();
+    s.push(".staging");
+    PathBuf::from(s)
+}
+
+
+fn previous_path(dir: &Path) -> PathBuf {
+    let mut s = dir.as_os_str().to_os_string();
+    s.push(".previous");
+    PathBuf::from(s)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::mpsc::channel;
+
+    // Serves the body to the given number of clients, standing in for
+    // GitHub. Supports "Range: bytes=<offset>-" and returns the offsets
+    // of the requests.
+    fn serve(body: Vec<u8>, clients: usize) -> (String, thread::JoinHandle<Vec<Option<usize>>>) {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let url = format!("http://{}/file", listener.local_addr().unwrap());
+	let server = thread::spawn(move || {
+	    let mut offsets = Vec::new();
+	    for _ in 0..clients {
+		let (stream, _) = listener.accept().unwrap();
+		let mut reader = BufReader::new(stream);
+		let mut line = String::new();
+		let mut offset = None;
+		while reader.read_line(&mut line).unwrap() > 2 {
+		    if let Some(range) = line.to_lowercase().strip_prefix("range: bytes=") {
+			offset = range.trim().strip_suffix('-').map(|x| x.parse::<usize>().unwrap());
+		    }
+		    line.clear();
+		}
+		offsets.push(offset);
+		let response = match offset {
+		    Some(n) if n >= body.len() => b"HTTP/1.1 416 Range Not Satisfiable\r\nContent-Length: 0\r\n\r\n".to_vec(),
+		    Some(n) => {
+			let header = format!("HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {n}-{}/{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len() - 1, body.len(), body.len() - n);
+			[header.as_bytes(), &body[n..]].concat()
+		    },
+		    None => {
+			let header = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+			[header.as_bytes(), &body[..]].concat()
+		    },
+		};
+		// The client may hang up early.
+		let _ = reader.get_mut().write_all(&response);
+	    }
+	    offsets
+	});
+	(url, server)
+    }
+
+    fn sha256(data: &[u8]) -> String {
+	format!("{:x}", Sha256::digest(data))
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+	let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+	encoder.write_all(data).unwrap();
+	encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn download_progress() {
+	let dir = env::temp_dir().join(format!("go-game-download-{}", std::process::id()));
+	fs::create_dir_all(&dir).unwrap();
+	let path = dir.join("model.bin");
+	let body: Vec<u8> = (0..1_000_000).map(|x| (x % 251) as u8).collect();
+	let (_tx, rx) = channel();
+
+	let (url, server) = serve(body.clone(), 1);
+	let mut reports = Vec::new();
+	assert!(download(&url, &path, None, &rx, &mut |p| reports.push(p.clone())).unwrap());
+	server.join().unwrap();
+	assert_eq!(fs::read(&path).unwrap(), body);
+	assert!(!part_path(&path).exists());
+	let last = reports.last().unwrap();
+	assert_eq!((last.done, last.total), (1_000_000, Some(1_000_000)));
+	assert_eq!(last.get_fraction(), Some(1.0));
+	assert!(!download(&url, &path, None, &rx, &mut |_| {}).unwrap());
+
+	let (tx, rx) = channel();
+	tx.send(()).unwrap();
+	let (url, server) = serve(body, 1);
+	let path = dir.join("cancelled.bin.gz");
+	let e = download(&url, &path, None, &rx, &mut |_| {}).unwrap_err();
+	server.join().unwrap();
+	assert_eq!(e.to_string(), CANCELLED);
+	assert!(!path.exists());
+	fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn download_checksum() {
+	let dir = env::temp_dir().join(format!("go-game-checksum-{}", std::process::id()));
+	fs::create_dir_all(&dir).unwrap();
+	let path = dir.join("katago.zip");
+	let body: Vec<u8> = (0..500_000).map(|x| (x % 253) as u8).collect();
+	let digest = sha256(&body);
+	let (_tx, rx) = channel();
+
+	// Resumes an interrupted download.
+	fs::write(part_path(&path), &body[..200_000]).unwrap();
+	let (url, server) = serve(body.clone(), 1);
+	assert!(download(&url, &path, Some(&digest), &rx, &mut |_| {}).unwrap());
+	assert_eq!(server.join().unwrap(), vec![Some(200_000)]);
+	assert_eq!(fs::read(&path).unwrap(), body);
+	assert_eq!(fs::read_to_string(digest_path(&path)).unwrap(), digest);
+
+	// A corrupt file is downloaded again, a corrupt partial file
+	// from scratch.
+	fs::write(&path, b"corrupt").unwrap();
+	fs::write(part_path(&path), vec![0; 100_000]).unwrap();
+	let (url, server) = serve(body.clone(), 2);
+	assert!(download(&url, &path, Some(&digest), &rx, &mut |_| {}).unwrap());
+	assert_eq!(server.join().unwrap(), vec![Some(100_000), None]);
+	assert_eq!(fs::read(&path).unwrap(), body);
+
+	// Recorded digests are checked when there is no pinned digest.
+	assert!(verify_file(&path, None, &rx).unwrap());
+	fs::write(&path, &body[1..]).unwrap();
+	assert!(!verify_file(&path, None, &rx).unwrap());
+	let (url, server) = serve(body.clone(), 2);
+	let e = download(&url, &path, Some(&sha256(b"other")), &rx, &mut |_| {}).unwrap_err();
+	assert_eq!(server.join().unwrap(), vec![None, None]);
+	assert!(e.to_string().contains("expected"), "{e}");
+	assert!(!path.exists() && !part_path(&path).exists());
+
+	// Without a pinned digest, a damaged network is not recorded as
+	// good.
+	let path = dir.join("model.bin.gz");
+	let (url, server) = serve(body.clone(), 2);
+	let e = download(&url, &path, None, &rx, &mut |_| {}).unwrap_err();
+	server.join().unwrap();
+	assert!(e.to_string().contains("damaged"), "{e}");
+	assert!(!path.exists() && !digest_path(&path).exists());
+
+	// A complete one passes and gets its digest recorded, a truncated
+	// one fails.
+	let gz = gzip(&body);
+	fs::write(&path, &gz).unwrap();
+	assert!(verify_file(&path, None, &rx).unwrap());
+	assert_eq!(fs::read_to_string(digest_path(&path)).unwrap(), sha256(&gz));
+	remove_download(&path).unwrap();
+	fs::write(&path, &gz[..gz.len() - 4]).unwrap();
+	assert!(!verify_file(&path, None, &rx).unwrap());
+	fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn offline_install() {
+	let dir = env::temp_dir().join(format!("go-game-offline-{}", std::process::id()));
+	let source = dir.join("source");
+	fs::create_dir_all(&source).unwrap();
+	let installer = KataGoInstaller::new(&dir.join("katago"));
+	installer.set_backend(Backend::Eigen).unwrap();
+	let pi = installer.get_path_info().unwrap();
+	let (_tx, rx) = channel();
+
+	let mut zip = zip::ZipWriter::new(fs::File::create(source.join("katago-mirror.zip")).unwrap());
+	// Reinstalling tests the update, which runs the fake engine.
+	zip.start_file(pi.katago_exe.file_name().unwrap().to_str().unwrap(), zip::write::SimpleFileOptions::default()).unwrap();
+	zip.write_all(format!("#!/bin/sh\nexec {}\n", crate::mock_engine::fake_engine_command("").trim()).as_bytes()).unwrap();
+	zip.start_file("default_gtp.cfg", zip::write::SimpleFileOptions::default()).unwrap();
+	zip.write_all(b"# Example\nlogDir = gtp_logs\nmaxVisits = 500  # Per move\n").unwrap();
+	zip.finish().unwrap();
+	let e = installer.install_offline(std::slice::from_ref(&source), &rx).unwrap_err();
+	assert!(e.contains("missing") && e.contains("analysis model"), "{e}");
+
+	fs::write(source.join(pi.analysis_model.file_name().unwrap()), b"not gzip").unwrap();
+	fs::write(source.join(pi.human_model.file_name().unwrap()), gzip(b"human")).unwrap();
+	let e = installer.install_offline(std::slice::from_ref(&source), &rx).unwrap_err();
+	assert!(e.contains("not a gzip"), "{e}");
+
+	fs::write(source.join(pi.analysis_model.file_name().unwrap()), gzip(b"analysis")).unwrap();
+	installer.install_offline(std::slice::from_ref(&source), &rx).unwrap();
+	assert!(installer.is_installed());
+	assert_eq!(fs::read(&pi.human_model).unwrap(), gzip(b"human"));
+	assert!(digest_path(&pi.katago_zip).exists());
+	let human_model = pi.human_model.file_name().unwrap().to_str().unwrap();
+	assert!(installer.get_networks_in_use().iter().any(|x| x == human_model));
+	assert!(installer.remove_network(human_model).is_err());
+	assert!(pi.human_model.exists());
+
+	// Configs are generated from the example of the release.
+	let analysis = installer.load_config(ConfigKind::Analysis).unwrap();
+	assert_eq!(analysis.get("logDir"), Some(dir.join("katago").join(LOGS_DIR).to_string_lossy().to_string()));
+	let mut human = KataGoConfig::load(&installer.get_config_path(ConfigKind::Human)).unwrap();
+	assert_eq!(human.to_string().lines().nth(2), Some("maxVisits = 40  # Per move"));
+	assert_eq!(human.get("humanSLProfile").as_deref(), Some("rank_5k"));
+	human.set("humanSLProfile", "preaz_1d");
+	installer.save_config(ConfigKind::Human, &human).unwrap();
+	installer.install_offline(std::slice::from_ref(&source), &rx).unwrap();
+	assert_eq!(installer.load_config(ConfigKind::Human).unwrap(), human);
+	let spec = pi.gtp_launch_spec(&pi.analysis_model, &installer.get_config_path(ConfigKind::Human), Some(&pi.human_model)).unwrap();
+	assert!(spec.to_command_str().ends_with(&format!("human.cfg -human-model {}", pi.human_model.display())));
+
+	// An older version recorded in the manifest can be upgraded, and
+	// rolled back to while it is installed.
+	let mut manifest = installer.get_manifest().unwrap();
+	let mut old = manifest.get_active().unwrap().clone();
+	assert_eq!((old.version.as_str(), old.backend.as_str()), (KATAGO_VERSION, "eigen"));
+	old.version = "v1.9.1".to_string();
+	manifest.add(old);
+	manifest.save(&dir.join("katago").join(MANIFEST_FILE)).unwrap();
+	let installer = KataGoInstaller::new(&dir.join("katago"));
+	assert_eq!(installer.get_version(), "v1.9.1");
+	assert_eq!(installer.get_update(), Some(KATAGO_VERSION));
+	assert!(!installer.is_installed());
+	installer.activate(KATAGO_VERSION, "eigen").unwrap();
+	assert_eq!(installer.get_update(), None);
+	assert!(installer.is_installed());
+	assert!(installer.activate("v1.9.1", "eigen").unwrap_err().contains("reinstall"));
+	assert!(installer.activate(KATAGO_VERSION, "opencl").is_err());
+	fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pinned_digests() {
+	let mut artifacts = vec![
+	    "g170e-b20c256x2-s5303129600-d1228401921.bin.gz".to_string(),
+	    "b18c384nbt-humanv0.bin.gz".to_string(),
+	];
+	for backend in Backend::ALL {
+	    for os in ["linux", "windows"] {
+		artifacts.push(format!("{}.zip", backend.archive_name(os, KATAGO_VERSION)));
+	    }
+	}
+	for (name, digest) in SHA256_DIGESTS {
+	    assert!(artifacts.iter().any(|x| x == name), "{name} is not a release artifact");
+	    assert!(digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase()), "{name}: {digest}");
+	}
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_rollback() {
+	use crate::mock_engine::fake_engine_command;
+	use std::os::unix::fs::PermissionsExt;
+
+	let dir = env::temp_dir().join(format!("go-game-extract-{}", std::process::id()));
+	let source = dir.join("source");
+	fs::create_dir_all(&source).unwrap();
+	let installer = KataGoInstaller::new(&dir.join("katago"));
+	installer.set_backend(Backend::Eigen).unwrap();
+	let pi = installer.get_path_info().unwrap();
+	let (_tx, rx) = channel();
+	fs::write(source.join(pi.analysis_model.file_name().unwrap()), gzip(b"analysis")).unwrap();
+	fs::write(source.join(pi.human_model.file_name().unwrap()), gzip(b"human")).unwrap();
+
+	let install = |entries: &[(&str, String, u32)]| {
+	    let mut zip = zip::ZipWriter::new(fs::File::create(source.join(pi.katago_zip.file_name().unwrap())).unwrap());
+	    for (name, content, mode) in entries {
+		zip.start_file(*name, zip::write::SimpleFileOptions::default().unix_permissions(*mode)).unwrap();
+		zip.write_all(content.as_bytes()).unwrap();
+	    }
+	    zip.finish().unwrap();
+	    let _ = remove_download(&pi.katago_zip);
+	    installer.install_offline(std::slice::from_ref(&source), &rx)
+	};
+	let good = format!("#!/bin/sh\nexec {}\n", fake_engine_command("").trim());
+	install(&[("katago", good.clone(), 0o700), ("README.txt", "KataGo".to_string(), 0o644)]).unwrap();
+	let mode = fs::metadata(&pi.katago_exe).unwrap().permissions().mode();
+	assert_eq!(mode & 0o777, 0o700);
+
+	// An update that fails the test is rolled back.
+	let e = install(&[("katago", "#!/bin/sh\nexit 1\n".to_string(), 0o644)]).unwrap_err();
+	assert!(e.contains("Restored the previous version"), "{e}");
+	assert_eq!(fs::read_to_string(&pi.katago_exe).unwrap(), good);
+	assert!(pi.katago_dir.join("README.txt").exists());
+
+	let e = install(&[("katago", good.clone(), 0o755), ("../evil", String::new(), 0o644)]).unwrap_err();
+	assert!(e.contains("unsafe path"), "{e}");
+	let e = install(&[("bin/katago", good.clone(), 0o755)]).unwrap_err();
+	assert!(e.contains("doesn't contain"), "{e}");
+	assert!(!dir.join("katago").join("evil").exists());
+	assert!(pi.katago_dir.join("README.txt").exists());
+
+	let update = format!("{good}# Update\n");
+	install(&[("katago", update.clone(), 0o755)]).unwrap();
+	assert_eq!(fs::read_to_string(&pi.katago_exe).unwrap(), update);
+	assert!(!pi.katago_dir.join("README.txt").exists());
+	assert!(!previous_path(&pi.katago_dir).exists() && !staging_path(&pi.katago_dir).exists());
+	fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn interrupted_swap_in() {
+	let dir = env::temp_dir().join(format!("go-game-swap-{}", std::process::id()));
+	let installer = KataGoInstaller::new(&dir);
+	installer.set_backend(Backend::Eigen).unwrap();
+	let katago_dir = installer.get_path_info().unwrap().katago_dir;
+
+	// State after the first rename of swap_in.
+	let previous = previous_path(&katago_dir);
+	let staging = staging_path(&katago_dir);
+	fs::create_dir_all(&previous).unwrap();
+	fs::write(previous.join("README.txt"), "KataGo").unwrap();
+	fs::create_dir_all(&staging).unwrap();
+	fs::write(staging.join("README.txt"), "Update").unwrap();
+
+	KataGoInstaller::new(&dir);
+	assert_eq!(fs::read_to_string(katago_dir.join("README.txt")).unwrap(), "KataGo");
+	assert!(!previous.exists() && !staging.exists());
+
+	// A leftover previous version next to the installed one is kept
+	// for swap_in to replace.
+	fs::create_dir_all(&previous).unwrap();
+	fs::create_dir_all(&staging).unwrap();
+	recover_swap_in(&katago_dir).unwrap();
+	assert!(katago_dir.join("README.txt").exists() && previous.exists() && !staging.exists());
+	fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn external() {
+	use crate::mock_engine::fake_engine_command;
+	use flate2::write::GzEncoder;
+	use std::os::unix::fs::PermissionsExt;
+
+	let dir = env::temp_dir().join(format!("go-game-external-{}", std::process::id()));
+	let bin = dir.join("bin");
+	fs::create_dir_all(&bin).unwrap();
+	let exe = bin.join("katago");
+	fs::write(&exe, format!("#!/bin/sh\nexec {}\n", fake_engine_command("").trim())).unwrap();
+	fs::set_permissions(&exe, fs::Permissions::from_mode(0o755)).unwrap();
+	let model = dir.join("b18c384nbt-test.bin.gz");
+	let mut encoder = GzEncoder::new(fs::File::create(&model).unwrap(), flate2::Compression::default());
+	encoder.write_all(b"b18c384nbt-test\n15\n").unwrap();
+	encoder.finish().unwrap();
+	fs::write(dir.join("katago"), "not executable").unwrap();
+	assert_eq!(find_katago(&[bin.clone(), dir.clone(), bin.join("..").join("bin")]), vec![fs::canonicalize(&exe).unwrap()]);
+
+	let installer = KataGoInstaller::new(&dir.join("app"));
+	installer.set_backend(Backend::Eigen).unwrap();
+	let (_tx, rx) = channel();
+	assert_eq!(installer.adopt_external(&exe, std::slice::from_ref(&model), &rx).unwrap(), "v0.1");
+	assert!(installer.is_installed() && installer.is_tuned());
+	assert_eq!(installer.get_update(), None);
+	let manifest = installer.get_manifest().unwrap();
+	assert_eq!(manifest.get_active().unwrap().backend, EXTERNAL_BACKEND);
+	assert_eq!(manifest.get_active().unwrap().models, vec!["b18c384nbt-test.bin.gz"]);
+
+	// A failing executable is not adopted.
+	let broken = dir.join("broken");
+	fs::write(&broken, "#!/bin/sh\nexit 1\n").unwrap();
+	fs::set_permissions(&broken, fs::Permissions::from_mode(0o755)).unwrap();
+	assert!(installer.adopt_external(&broken, &[], &rx).is_err());
+
+	let installer = KataGoInstaller::new(&dir.join("app"));
+	assert_eq!(installer.get_version(), "v0.1");
+	assert!(installer.is_installed());
+	installer.make_analysis_engine(None).unwrap().quit().unwrap();
+	fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backends() {
+	for backend in Backend::ALL {
+	    assert_eq!(Backend::from_name(backend.get_name()), Some(backend));
+	}
+	assert_eq!(Backend::detect().last(), Some(&Backend::Eigen));
+	assert!(!Backend::Eigen.needs_tuning());
+
+	let dir = env::temp_dir().join(format!("go-game-katago-{}", std::process::id()));
+	let installer = KataGoInstaller::new(&dir);
+	installer.set_backend(Backend::Eigen).unwrap();
+	let installer = KataGoInstaller::new(&dir);
+	assert_eq!(installer.get_backend(), Some(Backend::Eigen));
+	let pi = installer.get_path_info().unwrap();
+	assert!(pi.katago_url.ends_with(&format!("/{KATAGO_VERSION}/katago-{KATAGO_VERSION}-eigen-linux-x64.zip")) || cfg!(windows));
+	assert!(installer.is_tuned());
+	assert!(!installer.is_installed());
+
+	// An install from before the backend choice keeps OpenCL.
+	fs::remove_file(dir.join(BACKEND_FILE)).unwrap();
+	fs::create_dir_all(dir.join(Backend::OpenCL.archive_name(std::env::consts::OS, KATAGO_VERSION))).unwrap();
+	assert_eq!(KataGoInstaller::new(&dir).get_backend(), Some(Backend::OpenCL));
+	fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -1,25 +1,46 @@
 mod egui_view;
 mod model;
 mod katago_installer;
+mod katago_config;
+mod install_manifest;
+mod network_manager;
 mod smart_child;
 mod smart_thread;
 mod gtp;
+mod gtp_katago;
+mod gtp_channel;
+mod gtp_regress;
 mod child_process_engine;
+mod game_engine;
+mod engine_log;
+mod engine_config;
+mod gtp_transcript;
+mod socket_engine;
+#[cfg(test)]
+mod mock_engine;
 
 use crate::egui_view::EguiView;
 use dirs;
     
 
 fn main() -> Result<(), String> {
+    // Regression tests and replays run without the view.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|x| x.as_str()) {
+	Some("regress") => { return gtp_regress::run_from_args(&args[1..]); },
+	Some("replay") => { return gtp_transcript::run_from_args(&args[1..]); },
+	_ => (),
+    }
+
     println!("Starting Go.");
 
     // Setup directories to be used.
-    let katago_install_dir = dirs::home_dir().ok_or("Error at home_dir function!".to_string())?
-	.join(".cango").join("katago");
+    let app_dir = dirs::home_dir().ok_or("Error at home_dir function!".to_string())?
+	.join(".cango");
     
 
     // Start view.
-    EguiView::make(&katago_install_dir).unwrap().run();
+    EguiView::make(&app_dir).unwrap().run();
 
     println!("Exiting Go.");
     Ok(())
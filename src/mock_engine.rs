@@ -0,0 +1,203 @@
+use crate::gtp::*;
+use crate::gtp_katago::KataGoGtpExtensions;
+use crate::game_engine::GameEngine;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+
+// In-process engine for tests. Standard commands are answered with
+// canned responses. Tests can change responses, make commands fail
+// or take long, and make the engine crash.
+pub struct MockEngine {
+    id: u32,
+    responses: HashMap<String, Result<String, String>>, // Keyed by command name.
+    delays: HashMap<String, Duration>,
+    crash_after: Option<usize>, // Number of commands answered before the engine exits.
+    pending: RefCell<VecDeque<String>>, // Written but not yet answered commands.
+    commands: Vec<String>, // Answered commands without ids.
+    capabilities: Option<Capabilities>,
+}
+
+
+impl MockEngine {
+    pub fn new() -> Self {
+	let responses = [
+	    ("protocol_version", "2"),
+	    ("name", "Mock"),
+	    ("version", "1.0"),
+	    ("quit", ""),
+	    ("boardsize", ""),
+	    ("clear_board", ""),
+	    ("komi", ""),
+	    ("play", ""),
+	    ("genmove", "D4"),
+	    ("fixed_handicap", "D4 Q16 D16 Q4 K10"),
+	    ("place_free_handicap", "D4 Q16 D16 Q4 K10"),
+	    ("set_free_handicap", ""),
+	    ("loadsgf", ""),
+	    ("reg_genmove", "Q16"),
+	    ("undo", ""),
+	    ("time_settings", ""),
+	    ("time_left", ""),
+	    ("final_score", "W+2.5"),
+	    ("final_status_list", "D4 Q16"),
+	    ("showboard", "\n   A B\n 2 . . 2\n 1 X . 1\n   A B"),
+	];
+
+	Self {
+	    id: 0,
+	    responses: responses.iter().map(|(k, v)| (k.to_string(), Ok(v.to_string()))).collect(),
+	    delays: HashMap::new(),
+	    crash_after: None,
+	    pending: RefCell::new(VecDeque::new()),
+	    commands: Vec::new(),
+	    capabilities: None,
+	}
+    }
+
+    pub fn set_response(&mut self, command_name: &str, response: &str) {
+	self.responses.insert(command_name.to_string(), Ok(response.to_string()));
+    }
+
+    // Command is answered with '?' and the message.
+    pub fn set_failure(&mut self, command_name: &str, message: &str) {
+	self.responses.insert(command_name.to_string(), Err(message.to_string()));
+    }
+
+    pub fn set_delay(&mut self, command_name: &str, delay: Duration) {
+	self.delays.insert(command_name.to_string(), delay);
+    }
+
+    pub fn set_crash_after(&mut self, number_of_commands: usize) {
+	self.crash_after = Some(number_of_commands);
+    }
+
+    pub fn get_commands(&self) -> &[String] {
+	&self.commands
+    }
+
+    fn has_crashed(&self) -> bool {
+	self.crash_after.is_some_and(|n| self.commands.len() >= n)
+    }
+
+    fn respond(&self, command_name: &str, args: &str) -> Result<String, String> {
+	let mut names: Vec<_> = self.responses.keys().map(|x| x.as_str()).collect();
+	names.push("known_command");
+	names.push("list_commands");
+	names.sort();
+	names.dedup();
+
+	// Set responses take precedence, e.g. to fail list_commands.
+	match (command_name, self.responses.get(command_name)) {
+	    (_, Some(r)) => r.clone(),
+	    ("known_command", None) => Ok(names.contains(&args).to_string()),
+	    ("list_commands", None) => Ok(names.join("\n")),
+	    (_, None) => Err("unknown command".to_string()),
+	}
+    }
+}
+
+
+impl GTPEngineRaw for MockEngine {
+    fn gen_command_id(&mut self) -> u32 {
+	self.id += 1;
+	self.id
+    }
+
+    fn write_to_engine(&self, s: &str) -> Result<(), GtpError> {
+	if self.has_crashed() {
+	    return Err(GtpError::ProcessExited("Mock engine crashed.".to_string()));
+	}
+	self.pending.borrow_mut().push_back(s.trim().to_string());
+	Ok(())
+    }
+
+    fn read_from_engine(&mut self) -> Result<String, GtpError> {
+	if self.has_crashed() {
+	    return Err(GtpError::ProcessExited("Mock engine crashed.".to_string()));
+	}
+	let command = self.pending.borrow_mut().pop_front()
+	    .ok_or(GtpError::Io("Nothing was written to the mock engine!".to_string()))?;
+
+	let mut tokens = command.splitn(2, ' ');
+	let id = tokens.next().unwrap_or_default();
+	let command = tokens.next().unwrap_or_default();
+	let (command_name, args) = command.split_once(' ').unwrap_or((command, ""));
+
+	if let Some(delay) = self.delays.get(command_name) {
+	    thread::sleep(*delay);
+	}
+	let response = match self.respond(command_name, args) {
+	    Ok(s) => format!("={id} {s}"),
+	    Err(s) => format!("?{id} {s}"),
+	};
+	self.commands.push(command.to_string());
+	Ok(response)
+    }
+}
+
+
+impl GTPEngineMinimal for MockEngine {}
+
+
+impl GTPEngineTournament for MockEngine {}
+
+
+impl GTPEngineRegression for MockEngine {}
+
+
+impl GTPEngineExtendedCorePlay for MockEngine {}
+
+
+impl GTPEngineExtendedTournament for MockEngine {}
+
+
+impl GTPEngineDebug for MockEngine {}
+
+
+impl GTPEngineLeelaZero for MockEngine {}
+
+
+impl KataGoGtpExtensions for MockEngine {}
+
+
+// Lets the model drive the mock engine. Restarting forgets the crash.
+impl GameEngine for MockEngine {
+    fn get_capabilities(&self) -> Option<&Capabilities> {
+	self.capabilities.as_ref()
+    }
+
+    fn query_capabilities(&mut self) -> Result<&Capabilities, GtpError> {
+	if self.capabilities.is_none() {
+	    self.capabilities = Some(Capabilities::query(self)?);
+	}
+	Ok(self.capabilities.as_ref().unwrap())
+    }
+
+    fn record_transcript(&mut self, _dir: &Path) -> Result<PathBuf, String> {
+	Err("Mock engine doesn't record transcripts!".to_string())
+    }
+
+    fn set_command_timeout(&mut self, _timeout: Option<Duration>) {}
+
+    fn restart(&mut self) -> Result<(), GtpError> {
+	self.crash_after = None;
+	self.pending.borrow_mut().clear();
+	Ok(())
+    }
+}
+
+
+// Command line of the fake engine binary, for tests of engines that
+// run in a child process. Cargo puts binaries one level above the
+// directory of the test executable.
+pub fn fake_engine_command(args: &str) -> String {
+    let test_exe = env::current_exe().unwrap();
+    let engine_exe = test_exe.parent().unwrap().parent().unwrap()
+	.join(format!("fake_gtp_engine{}", env::consts::EXE_SUFFIX));
+    format!("{} {args}", engine_exe.to_str().unwrap())
+}
@@ -1,6 +1,17 @@
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 use std::iter::Rev;
-use crate::child_process_engine::ChildProcessEngine;
+use std::process::ExitStatus;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::game_engine::GameEngine;
+use crate::engine_log::EngineLog;
+use crate::gtp::{self, Capability, GTPMove, GtpError, MoveInfo, Vertex};
+use crate::gtp_channel::{GtpChannel, PendingResponse, ResponseEvent};
+use crate::gtp_katago::KataAnalysis;
+
+
+// Time an interrupted analysis gets to end its response.
+const ANALYSIS_STOP_TIMEOUT: Duration = Duration::from_secs(10);
 
 
 pub struct Model {
@@ -9,8 +20,13 @@ pub struct Model {
     history: History, // doesn't store the current board
     black_captures: i32, // number of stones that black captured
     white_captures: i32,
-    analysis_engine: Option<ChildProcessEngine>,
-    human_engine: Option<ChildProcessEngine>,
+    analysis_engine: Option<Box<dyn GameEngine>>, // Engines are shut down when the model is dropped.
+    human_engine: Option<Box<dyn GameEngine>>,
+    analysis_engine_moves: Option<Vec<GTPMove>>, // Position the analysis engine has, None if unknown.
+    analysis: Option<Analysis>,
+    analysis_job: Option<AnalysisJob>, // Running analysis, which has the analysis engine meanwhile.
+    last_desync: Option<String>, // Last repaired difference between the board of an engine and ours.
+    black_winrates: BTreeMap<usize, f32>, // Keyed by move count.
 }
 
 #[derive(Clone, PartialEq)]
@@ -52,9 +68,55 @@ pub enum Turn {
     White
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum EngineSlot {
+    Analysis,
+    Human,
+}
+
+#[derive(Clone)]
+struct Analysis {
+    board: Board, // Position that was analyzed.
+    turn: Turn,
+    moves: Vec<AnalyzedMove>,
+}
+
+// Analysis that streams through the channel of the analysis engine,
+// so that the view keeps drawing. A worker thread syncs the engine to
+// the position and starts the analysis, then hands the engine back.
+// Reports are taken as they arrive until the analysis is interrupted
+// after its duration.
+struct AnalysisJob {
+    board: Board,
+    turn: Turn,
+    move_count: usize,
+    moves: Vec<GTPMove>, // Position the engine has once synced.
+    log: Option<EngineLog>, // Of the engine, shown while it is away.
+    channel: GtpChannel,
+    duration: Duration,
+    setup: Option<thread::JoinHandle<AnalysisSetup>>, // None once the analysis streams.
+    stream: Option<(Capability, PendingResponse, Instant)>, // Analysis command, its response and when to interrupt it.
+    interrupted: Option<Instant>,
+}
+
+// Engine handed back by the setup of an analysis, with the analysis
+// command, its response and the repaired desync, if any.
+type AnalysisSetup = (Box<dyn GameEngine>, Result<(Capability, PendingResponse, Option<String>), String>);
+
+// Candidate move of the analysis engine. Winrate and score lead are
+// from the perspective of the player to move.
+#[derive(Clone)]
+pub struct AnalyzedMove {
+    pub point: Option<(usize, usize)>, // None for pass.
+    pub visits: u32,
+    pub winrate: f32,
+    pub score_lead: Option<f32>,
+    pub order: u32, // 0 for the best move.
+}
+
 
 impl Model {
-    pub fn make_model(board_size: usize, analysis_engine: Option<ChildProcessEngine>, human_engine: Option<ChildProcessEngine>) -> Self {
+    pub fn make_model(board_size: usize, analysis_engine: Option<Box<dyn GameEngine>>, human_engine: Option<Box<dyn GameEngine>>) -> Self {
 	Self {
 	    board: Board::make_empty_board(board_size),
 	    turn: Turn::Black,
@@ -63,6 +125,11 @@ impl Model {
 	    white_captures: 0,
 	    analysis_engine: analysis_engine,
 	    human_engine: human_engine,
+	    analysis_engine_moves: None,
+	    analysis: None,
+	    analysis_job: None,
+	    last_desync: None,
+	    black_winrates: BTreeMap::new(),
 	}
     }
 
@@ -151,6 +218,9 @@ impl Model {
 		    Turn::White => { self.white_captures += captures; }
 		}
 		self.switch_turn();
+		// Winrates after this move belonged to another variation.
+		let n = self.get_move_count();
+		self.black_winrates.retain(|&k, _| k < n);
 		Ok(())
 	    }
 	} else {
@@ -222,6 +292,350 @@ impl Model {
 	    None
 	}
     }
+
+    fn get_engine_mut(&mut self, slot: EngineSlot) -> Option<&mut dyn GameEngine> {
+	match slot {
+	    EngineSlot::Analysis => self.analysis_engine.as_mut().map(|x| &mut **x as &mut dyn GameEngine),
+	    EngineSlot::Human => self.human_engine.as_mut().map(|x| &mut **x as &mut dyn GameEngine),
+	}
+    }
+
+    fn get_engine(&self, slot: EngineSlot) -> Option<&dyn GameEngine> {
+	match slot {
+	    EngineSlot::Analysis => self.analysis_engine.as_deref(),
+	    EngineSlot::Human => self.human_engine.as_deref(),
+	}
+    }
+
+    pub fn has_engine(&self, slot: EngineSlot) -> bool {
+	self.get_engine(slot).is_some() || (slot == EngineSlot::Analysis && self.is_analyzing())
+    }
+
+    // None until the engine has been asked for its commands, which
+    // happens on first use, and for engines that don't list them.
+    pub fn engine_has_capability(&self, slot: EngineSlot, capability: Capability) -> Option<bool> {
+	self.get_engine(slot)?.get_capabilities().filter(|x| x.is_listed()).map(|x| x.has(capability))
+    }
+
+    pub fn get_engine_log(&self, slot: EngineSlot) -> Vec<String> {
+	let log = match (self.get_engine(slot), &self.analysis_job) {
+	    (Some(engine), _) => engine.get_log(),
+	    (None, Some(job)) if slot == EngineSlot::Analysis => job.log.as_ref(),
+	    (None, _) => None,
+	};
+	log.map(|log| log.get_lines()).unwrap_or_default()
+    }
+
+    // Returns the exit status if the engine in the slot has exited.
+    pub fn engine_exit_status(&mut self, slot: EngineSlot) -> Option<ExitStatus> {
+	self.get_engine_mut(slot).and_then(|engine| engine.exit_status())
+    }
+
+    // Relaunches the engine with the same command and replays the
+    // current position so that the game can continue. The board of the
+    // engine is checked afterwards, like after every sync.
+    pub fn restart_engine(&mut self, slot: EngineSlot) -> Result<(), String> {
+	let board = self.board.clone();
+	let moves = self.get_position_as_gtp_moves();
+	if slot == EngineSlot::Analysis && self.is_analyzing() {
+	    return Err("Analysis engine is busy analyzing!".to_string());
+	}
+	let engine = self.get_engine_mut(slot).ok_or(format!("No {slot:?} engine to restart!"))?;
+	engine.restart()?;
+	replay_position(engine, board.size, &moves)?;
+	if let Some(s) = check_engine_board(engine, &board, &moves)? {
+	    self.last_desync = Some(s);
+	}
+	if slot == EngineSlot::Analysis {
+	    self.analysis_engine_moves = Some(moves);
+	}
+	Ok(())
+    }
+
+    // Starts analyzing the current position for duration with
+    // kata-analyze or lz-analyze, whichever the analysis engine knows.
+    // poll_analysis takes the reports as they arrive.
+    pub fn start_analysis(&mut self, duration: Duration) -> Result<(), String> {
+	if self.is_analyzing() {
+	    return Err("Analysis is already running!".to_string());
+	}
+	let channel = self.analysis_engine.as_ref().ok_or("No analysis engine!".to_string())?
+	    .get_channel().ok_or("Analysis engine cannot stream analysis!".to_string())?;
+	let mut engine = self.analysis_engine.take().unwrap();
+	let log = engine.get_log().cloned();
+	let engine_moves = self.analysis_engine_moves.take();
+	let moves = self.get_position_as_gtp_moves();
+	let (board, turn) = (self.board.clone(), self.turn);
+
+	let job_moves = moves.clone();
+	let job_board = board.clone();
+	let handle = thread::spawn(move || {
+	    let r = begin_analysis(engine.as_mut(), engine_moves, &job_board, turn, &job_moves);
+	    (engine, r)
+	});
+	self.analysis_job = Some(AnalysisJob {
+	    board: board,
+	    turn: turn,
+	    move_count: self.get_move_count(),
+	    moves: moves,
+	    log: log,
+	    channel: channel,
+	    duration: duration,
+	    setup: Some(handle),
+	    stream: None,
+	    interrupted: None,
+	});
+	Ok(())
+    }
+
+    pub fn is_analyzing(&self) -> bool {
+	self.analysis_job.is_some()
+    }
+
+    // Takes the reports that have arrived without blocking and
+    // interrupts the analysis once its time is up. Meant to be called
+    // every frame. Returns the error of a failed analysis.
+    pub fn poll_analysis(&mut self) -> Result<(), String> {
+	let r = self.poll_analysis_job();
+	if !matches!(r, Ok(false)) {
+	    self.analysis_job = None;
+	}
+	r.map(|_| ())
+    }
+
+    // Returns whether the analysis has ended.
+    fn poll_analysis_job(&mut self) -> Result<bool, String> {
+	let job = match self.analysis_job.as_mut() {
+	    Some(job) => job,
+	    None => { return Ok(false); },
+	};
+
+	if job.setup.as_ref().is_some_and(|x| x.is_finished()) {
+	    let (engine, r) = job.setup.take().unwrap().join()
+		.map_err(|_| "Analysis thread panicked! Analysis engine is lost.".to_string())?;
+	    self.analysis_engine = Some(engine);
+	    // After a failure the position of the engine is unknown, it
+	    // is replayed next time.
+	    let (command, response, desync) = r?;
+	    self.analysis_engine_moves = Some(job.moves.clone());
+	    if desync.is_some() {
+		self.last_desync = desync;
+	    }
+	    job.stream = Some((command, response, Instant::now() + job.duration));
+	}
+	let (command, response, deadline) = match &job.stream {
+	    Some(x) => x,
+	    None => { return Ok(false); },
+	};
+
+	while let Some(event) = response.try_next() {
+	    let line = match event {
+		ResponseEvent::Line(line) => line,
+		ResponseEvent::Done(r) => {
+		    r?;
+		    return Ok(true);
+		},
+	    };
+	    if !line.starts_with("info") {
+		continue;
+	    }
+	    let move_infos = match command {
+		Capability::LzAnalyze => gtp::parse_lz_analysis(&line)?,
+		_ => KataAnalysis::from_string(&line)?.move_infos,
+	    };
+	    let moves: Vec<_> = move_infos.iter().map(|info| to_analyzed_move(info, job.board.size)).collect();
+
+	    if let Some(best) = moves.iter().find(|m| m.order == 0) {
+		let black_winrate = match job.turn {
+		    Turn::Black => best.winrate,
+		    Turn::White => 1.0 - best.winrate,
+		};
+		// Moves made meanwhile may have started another variation.
+		if job.board == self.board && job.turn == self.turn {
+		    self.black_winrates.insert(job.move_count, black_winrate);
+		}
+	    }
+	    self.analysis = Some(Analysis {
+		board: job.board.clone(),
+		turn: job.turn,
+		moves: moves,
+	    });
+	}
+
+	match job.interrupted {
+	    None if Instant::now() >= *deadline => {
+		job.channel.interrupt()?;
+		job.interrupted = Some(Instant::now());
+	    },
+	    Some(t) if t.elapsed() > ANALYSIS_STOP_TIMEOUT => {
+		return Err("Analysis engine didn't stop analyzing!".to_string());
+	    },
+	    _ => (),
+	}
+	Ok(false)
+    }
+
+    // Candidate moves if the current position has been analyzed.
+    pub fn get_analysis(&self) -> &[AnalyzedMove] {
+	match &self.analysis {
+	    Some(a) if a.board == self.board && a.turn == self.turn => &a.moves,
+	    _ => &[],
+	}
+    }
+
+    pub fn get_last_desync(&self) -> Option<&str> {
+	self.last_desync.as_deref()
+    }
+
+    // Winrates of black at the analyzed positions as (move count,
+    // winrate) pairs.
+    pub fn get_black_winrates(&self) -> Vec<(usize, f32)> {
+	self.black_winrates.iter().map(|(k, v)| (*k, *v)).collect()
+    }
+
+    // Stones of the position the history starts from, followed by
+    // the moves played since.
+    fn get_position_as_gtp_moves(&self) -> Vec<GTPMove> {
+	let size = self.get_board_size();
+	let start = match self.history.first() {
+	    Some(item) => &item.board,
+	    None => &self.board,
+	};
+
+	let mut moves = Vec::new();
+	for x in 0..size {
+	    for y in 0..size {
+		let color = match start.get(x, y) {
+		    Ok(Point::Black) => gtp::Color::Black,
+		    Ok(Point::White) => gtp::Color::White,
+		    _ => { continue; },
+		};
+		moves.push(GTPMove::new(color, to_gtp_vertex(x, y, size)));
+	    }
+	}
+	for item in self.history.in_order() {
+	    let (x, y) = item.gomove;
+	    moves.push(GTPMove::new(to_gtp_color(item.turn), to_gtp_vertex(x, y, size)));
+	}
+	moves
+    }
+}
+
+
+// Syncs the engine to moves, checks its board and starts analyzing.
+// Returns the analysis command, its response and the repaired
+// desync, if any.
+fn begin_analysis(engine: &mut dyn GameEngine, engine_moves: Option<Vec<GTPMove>>, board: &Board, turn: Turn, moves: &[GTPMove]) -> Result<(Capability, PendingResponse, Option<String>), String> {
+    let capabilities = engine.query_capabilities()?.clone();
+    let command = if capabilities.check(engine, Capability::KataAnalyze)? {
+	Capability::KataAnalyze
+    } else if capabilities.check(engine, Capability::LzAnalyze)? {
+	Capability::LzAnalyze
+    } else {
+	return Err("Analysis engine supports neither kata-analyze nor lz-analyze!".to_string());
+    };
+
+    sync_position(engine, engine_moves, board.size, moves)?;
+    let desync = check_engine_board(engine, board, moves)?;
+
+    let color = to_gtp_color(turn);
+    let response = match command {
+	Capability::LzAnalyze => engine.lz_analyze(color, 10)?,
+	_ => engine.kata_analyze(color, 10, false)?,
+    };
+    Ok((command, response, desync))
+}
+
+
+fn replay_position(engine: &mut dyn GameEngine, size: usize, moves: &[GTPMove]) -> Result<(), GtpError> {
+    engine.boardsize(size as u32)?;
+    engine.clear_board()?;
+    for m in moves {
+	engine.play(m.clone())?;
+    }
+    Ok(())
+}
+
+
+// Compares the board of the engine with board and replays the
+// position if they differ. Returns the difference that was repaired.
+fn check_engine_board(engine: &mut dyn GameEngine, board: &Board, moves: &[GTPMove]) -> Result<Option<String>, String> {
+    if !engine.query_capabilities()?.has(Capability::Showboard) {
+	return Ok(None);
+    }
+    let engine_board = match Board::from_showboard(&engine.showboard()?) {
+	Ok(b) => b,
+	Err(s) => {
+	    println!("Cannot check the engine board: {s}");
+	    return Ok(None);
+	},
+    };
+    let diff = board.diff(&engine_board);
+    if diff.is_empty() {
+	return Ok(None);
+    }
+
+    let vertices: Vec<_> = diff.iter()
+	.filter_map(|(x, y)| to_gtp_vertex(*x, *y, board.size).to_string().ok())
+	.collect();
+    let s = format!("Engine board differs at {}.", vertices.join(" "));
+    println!("{s} Replaying the position.");
+    replay_position(engine, board.size, moves)?;
+    if !board.diff(&Board::from_showboard(&engine.showboard()?)?).is_empty() {
+	return Err(format!("{s} Replaying the position didn't repair it."));
+    }
+    Ok(Some(s))
+}
+
+
+// Brings the engine from engine_moves to moves. Continues or undoes
+// the moves the engine has when possible, otherwise replays the
+// position, e.g. for engines without undo.
+fn sync_position(engine: &mut dyn GameEngine, engine_moves: Option<Vec<GTPMove>>, size: usize, moves: &[GTPMove]) -> Result<(), GtpError> {
+    match engine_moves {
+	Some(old) if moves.starts_with(&old) => {
+	    for m in &moves[old.len()..] {
+		engine.play(m.clone())?;
+	    }
+	    Ok(())
+	},
+	Some(old) if old.starts_with(moves) && engine.query_capabilities()?.has(Capability::Undo) => {
+	    for _ in moves.len()..old.len() {
+		engine.undo()?;
+	    }
+	    Ok(())
+	},
+	_ => replay_position(engine, size, moves),
+    }
+}
+
+
+fn to_gtp_color(turn: Turn) -> gtp::Color {
+    match turn {
+	Turn::Black => gtp::Color::Black,
+	Turn::White => gtp::Color::White,
+    }
+}
+
+
+fn to_gtp_vertex(x: usize, y: usize, size: usize) -> Vertex {
+    // Model has its origin at top-left, GTP at bottom-left.
+    Vertex::Coordinate(x as u32, (size - 1 - y) as u32)
+}
+
+
+fn to_analyzed_move(info: &MoveInfo, size: usize) -> AnalyzedMove {
+    let point = match info.vertex {
+	Vertex::Coordinate(x, y) if (x as usize) < size && (y as usize) < size => Some((x as usize, size - 1 - y as usize)),
+	_ => None,
+    };
+    AnalyzedMove {
+	point: point,
+	visits: info.visits,
+	winrate: info.winrate,
+	score_lead: info.score_lead,
+	order: info.order,
+    }
 }
 
 
@@ -238,11 +652,36 @@ impl Clone for Model {
 	    white_captures: self.white_captures,
 	    analysis_engine: None,
 	    human_engine: None,
+	    analysis_engine_moves: None,
+	    analysis: self.analysis.clone(),
+	    analysis_job: None,
+	    last_desync: None,
+	    black_winrates: self.black_winrates.clone(),
 	}
     }
 }
 
 
+impl Drop for Model {
+    // Engines shut down on their own when dropped. Dropping them in
+    // parallel halves the wait for engines that don't answer quit.
+    fn drop(&mut self) {
+	let engines = [self.analysis_engine.take(), self.human_engine.take()];
+	let job = self.analysis_job.take();
+	thread::scope(|s| {
+	    for engine in engines.into_iter().flatten() {
+		s.spawn(move || drop(engine));
+	    }
+	    // Engine of an analysis that is being set up is dropped once
+	    // the setup is done.
+	    if let Some(setup) = job.and_then(|x| x.setup) {
+		s.spawn(move || drop(setup.join()));
+	    }
+	});
+    }
+}
+
+
 impl Board {
     fn make_empty_board(board_size: usize) -> Self {
 	Board {
@@ -265,6 +704,62 @@ impl Board {
 	Ok(())
     }
 
+    // Parses the board in the output of showboard. Understands the
+    // formats of KataGo and GNU Go, in which rows start with their
+    // number and stones are X and O. Markers of recent moves are
+    // ignored.
+    pub fn from_showboard(s: &str) -> Result<Self, String> {
+	let mut board: Option<Board> = None;
+	let mut rows = 0;
+	for line in s.lines() {
+	    let line = line.trim_start();
+	    let rest = line.trim_start_matches(|c: char| c.is_ascii_digit());
+	    let Ok(n) = line[..line.len() - rest.len()].parse::<usize>() else { continue; };
+
+	    // Top row has the number of the board size.
+	    let board = board.get_or_insert_with(|| Board::make_empty_board(n));
+	    let size = board.size;
+	    if n != size - rows {
+		return Err(format!("Unexpected row {n} in showboard output!"));
+	    }
+	    let points: Vec<_> = rest.chars().filter_map(|c| match c {
+		'X' | 'x' => Some(Point::Black),
+		'O' | 'o' => Some(Point::White),
+		'.' | '+' => Some(Point::Empty),
+		_ => None,
+	    }).take(size).collect();
+	    if points.len() != size {
+		return Err(format!("Row {n} of showboard output has {} points!", points.len()));
+	    }
+	    for (x, p) in points.into_iter().enumerate() {
+		board.set(x, size - n, p)?;
+	    }
+	    rows += 1;
+	    if rows == size {
+		break;
+	    }
+	}
+
+	match board {
+	    Some(b) if rows == b.size => Ok(b),
+	    _ => Err("Showboard output doesn't contain a complete board!".to_string()),
+	}
+    }
+
+    // Points that differ between the boards. All points differ if
+    // the sizes do.
+    pub fn diff(&self, other: &Board) -> Vec<(usize, usize)> {
+	let mut v = Vec::new();
+	for x in 0..self.size {
+	    for y in 0..self.size {
+		if self.size != other.size || self.matrix[x][y] != other.matrix[x][y] {
+		    v.push((x, y));
+		}
+	    }
+	}
+	v
+    }
+
     fn remove_stone(&mut self, x: usize, y: usize) -> Result<(), String> {
 	self.set(x, y, Point::Empty)
     }
@@ -488,6 +983,14 @@ impl History {
 	self.items.iter().rev()
     }
 
+    fn in_order(&self) -> std::slice::Iter<'_, HistoryItem> {
+	self.items.iter()
+    }
+
+    fn first(&self) -> Option<&HistoryItem> {
+	self.items.first()
+    }
+
     fn last(&self) -> Option<&HistoryItem> {
 	self.items.last()
     }
@@ -501,3 +1004,79 @@ fn opposite(point: Point) -> Option<Point> {
 	Point::Empty => None
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::child_process_engine::ChildProcessEngine;
+    use crate::mock_engine::{MockEngine, fake_engine_command};
+
+    #[test]
+    fn showboard() {
+	let katago = "MoveNum: 3 HASH: 5F0E7A\n   A B C D E\n 5 . . . . .\n 4 . O2. . .\n 3 . . X3. .\n 2 . X1. . .\n 1 . . . . .\n\nNext player: White\nB stones captured: 0\nW stones captured: 0";
+	let board = Board::from_showboard(katago).unwrap();
+	assert_eq!(board.size, 5);
+	assert_eq!(board.get(1, 1).unwrap(), Point::White); // B4
+	assert_eq!(board.get(2, 2).unwrap(), Point::Black); // C3
+	assert_eq!(board.get(1, 3).unwrap(), Point::Black); // B2
+	assert_eq!(board.get(0, 0).unwrap(), Point::Empty);
+
+	let gnugo = "\n   A B C D E\n 5 . . . . . 5\n 4 . O . . . 4     WHITE (O) has captured 0 stones\n 3 . . + . . 3\n 2 . X(X). . 2     BLACK (X) has captured 0 stones\n 1 . . . . . 1\n   A B C D E";
+	let other = Board::from_showboard(gnugo).unwrap();
+	assert_eq!(board.diff(&other), vec![(2, 2), (2, 3)]);
+
+	assert!(Board::from_showboard(" 5 . . . . .\n 4 . . . . .").is_err());
+	assert!(Board::from_showboard("\n   A B\n 2 . . 2\n 1 X . 1\n   A B").is_ok());
+    }
+
+    #[test]
+    fn analysis() {
+	let engine = ChildProcessEngine::new(&fake_engine_command("--analyze kata-analyze")).unwrap();
+	let mut model = Model::make_model(2, Some(Box::new(engine)), None);
+	model.make_move(0, 1).unwrap(); // A1, as on the board of the fake engine.
+
+	model.start_analysis(Duration::from_millis(100)).unwrap();
+	assert!(model.has_engine(EngineSlot::Analysis));
+	assert!(model.start_analysis(Duration::ZERO).is_err());
+	while model.is_analyzing() {
+	    model.poll_analysis().unwrap();
+	    thread::sleep(Duration::from_millis(10));
+	}
+	let analysis = model.get_analysis();
+	assert_eq!(analysis.len(), 1);
+	assert_eq!(analysis[0].point, Some((1, 0)));
+	assert_eq!(model.get_black_winrates(), vec![(1, 0.25)]);
+	assert_eq!(model.get_last_desync(), None);
+	assert_eq!(model.engine_has_capability(EngineSlot::Analysis, Capability::KataAnalyze), Some(true));
+
+	// The interrupted engine takes the next analysis.
+	model.start_analysis(Duration::ZERO).unwrap();
+	while model.is_analyzing() {
+	    model.poll_analysis().unwrap();
+	    thread::sleep(Duration::from_millis(10));
+	}
+
+	// Engines that answer one command at a time cannot stream.
+	let mut model = Model::make_model(2, Some(Box::new(MockEngine::new())), None);
+	assert!(model.start_analysis(Duration::ZERO).is_err());
+	assert!(model.has_engine(EngineSlot::Analysis));
+    }
+
+    #[test]
+    fn restart() {
+	let mut engine = MockEngine::new();
+	engine.set_crash_after(0);
+	let mut model = Model::make_model(2, None, Some(Box::new(engine)));
+	model.make_move(0, 1).unwrap();
+	model.restart_engine(EngineSlot::Human).unwrap();
+
+	// Replaying cannot repair a board that stays empty.
+	let mut engine = MockEngine::new();
+	engine.set_response("showboard", "\n   A B\n 2 . . 2\n 1 . . 1\n   A B");
+	let mut model = Model::make_model(2, Some(Box::new(engine)), None);
+	model.make_move(0, 1).unwrap();
+	let s = model.restart_engine(EngineSlot::Analysis).unwrap_err();
+	assert!(s.contains("differs at A1"));
+    }
+}
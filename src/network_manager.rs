@@ -0,0 +1,240 @@
+// Neural networks of KataGo, kept in one directory. A network is a
+// gzipped ".bin.gz" or ".txt.gz" file, both starting with the model
+// name and format version as text lines.
+
+use flate2::read::GzDecoder;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+
+pub const NETWORK_EXTENSIONS: [&str; 2] = [".bin.gz", ".txt.gz"];
+
+
+#[derive(Clone)]
+pub struct NetworkManager {
+    dir: PathBuf,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NetworkInfo {
+    pub file_name: String,
+    pub path: PathBuf,
+    pub size: u64, // Bytes of the gzipped file.
+    pub model_name: Option<String>, // From the file header, None if it cannot be read.
+    pub format_version: Option<u32>,
+    pub blocks: Option<u32>, // From names like "b18c384nbt" or "b20c256x2".
+    pub channels: Option<u32>,
+    pub is_human: bool, // Human SL network, imitating players of a rank.
+}
+
+
+impl NetworkManager {
+    pub fn new(dir: &Path) -> Self {
+	Self {
+	    dir: dir.to_path_buf(),
+	}
+    }
+
+    pub fn get_dir(&self) -> &Path {
+	&self.dir
+    }
+
+    pub fn get_path(&self, file_name: &str) -> PathBuf {
+	self.dir.join(file_name)
+    }
+
+    // Networks sorted by file name. A missing directory has none.
+    pub fn list(&self) -> Result<Vec<NetworkInfo>, String> {
+	if !self.dir.exists() {
+	    return Ok(Vec::new());
+	}
+	let entries = fs::read_dir(&self.dir).map_err(|e| format!("Cannot read directory {:?}: {e}", self.dir))?;
+	let mut networks: Vec<_> = entries.filter_map(|x| x.ok())
+	    .map(|x| x.path())
+	    .filter(|x| x.is_file() && is_network_file(x))
+	    .map(|x| NetworkInfo::read(&x))
+	    .collect::<Result<_, _>>()?;
+	networks.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+	Ok(networks)
+    }
+
+    // Links a local network file into the directory, or copies it if
+    // it is on another file system.
+    pub fn add_file(&self, path: &Path) -> Result<NetworkInfo, String> {
+	if !is_network_file(path) {
+	    return Err(format!("{path:?} is not a network file, expected one of {NETWORK_EXTENSIONS:?}!"));
+	}
+	let info = NetworkInfo::read(path)?;
+	if info.model_name.is_none() {
+	    return Err(format!("{path:?} is not a valid network!"));
+	}
+	fs::create_dir_all(&self.dir).map_err(|e| format!("Cannot create directory {:?}: {e}", self.dir))?;
+	let target = self.get_path(&info.file_name);
+	if target.exists() {
+	    if fs::canonicalize(&target).ok() == fs::canonicalize(path).ok() {
+		return Ok(info);
+	    }
+	    fs::remove_file(&target).map_err(|e| format!("Cannot remove {target:?}: {e}"))?;
+	}
+	if fs::hard_link(path, &target).is_err() {
+	    fs::copy(path, &target).map_err(|e| format!("Cannot copy {path:?} to {target:?}: {e}"))?;
+	}
+	NetworkInfo::read(&target)
+    }
+
+    // Removes the network and its recorded digest.
+    pub fn remove(&self, file_name: &str) -> Result<(), String> {
+	let path = self.get_path(file_name);
+	fs::remove_file(&path).map_err(|e| format!("Cannot remove {path:?}: {e}"))?;
+	let _ = fs::remove_file(self.get_path(&format!("{file_name}.sha256")));
+	Ok(())
+    }
+
+    // Moves networks and their digests from old_dir, where installs
+    // before the network manager kept them. Returns the moved networks.
+    pub fn migrate(&self, old_dir: &Path) -> Result<Vec<String>, String> {
+	let Ok(entries) = fs::read_dir(old_dir) else {
+	    return Ok(Vec::new());
+	};
+	let mut moved = Vec::new();
+	for path in entries.filter_map(|x| x.ok()).map(|x| x.path()) {
+	    if !path.is_file() || !is_network_file(&path) {
+		continue;
+	    }
+	    fs::create_dir_all(&self.dir).map_err(|e| format!("Cannot create directory {:?}: {e}", self.dir))?;
+	    let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+	    for name in [file_name.clone(), format!("{file_name}.sha256")] {
+		let from = old_dir.join(&name);
+		if from.exists() {
+		    fs::rename(&from, self.get_path(&name)).map_err(|e| format!("Cannot move {from:?}: {e}"))?;
+		}
+	    }
+	    println!("Moved network {file_name} to {:?}.", self.dir);
+	    moved.push(file_name);
+	}
+	Ok(moved)
+    }
+}
+
+
+impl NetworkInfo {
+    pub fn read(path: &Path) -> Result<Self, String> {
+	let file_name = path.file_name().ok_or(format!("{path:?} has no file name!"))?.to_string_lossy().to_string();
+	let size = fs::metadata(path).map_err(|e| format!("Cannot read {path:?}: {e}"))?.len();
+	let (model_name, format_version) = match read_header(path) {
+	    Some((name, version)) => (Some(name), Some(version)),
+	    None => (None, None),
+	};
+	let (blocks, channels) = parse_architecture(model_name.as_deref().unwrap_or(&file_name));
+	Ok(Self {
+	    is_human: file_name.contains("human"),
+	    file_name: file_name,
+	    path: path.to_path_buf(),
+	    size: size,
+	    model_name: model_name,
+	    format_version: format_version,
+	    blocks: blocks,
+	    channels: channels,
+	})
+    }
+
+    // E.g. "b18c384, 98.4 MB".
+    pub fn get_description(&self) -> String {
+	let mut s = String::new();
+	if let (Some(blocks), Some(channels)) = (self.blocks, self.channels) {
+	    s.push_str(&format!("b{blocks}c{channels}, "));
+	}
+	if self.is_human {
+	    s.push_str("human, ");
+	}
+	s.push_str(&format!("{:.1} MB", self.size as f64 / (1024.0 * 1024.0)));
+	s
+    }
+}
+
+
+pub fn is_network_file(path: &Path) -> bool {
+    let name = path.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+    NETWORK_EXTENSIONS.iter().any(|x| name.ends_with(x))
+}
+
+
+// Model name and format version from the first two lines.
+fn read_header(path: &Path) -> Option<(String, u32)> {
+    let file = fs::File::open(path).ok()?;
+    let mut reader = BufReader::new(GzDecoder::new(file).take(1024));
+    let mut name = String::new();
+    let mut version = String::new();
+    reader.read_line(&mut name).ok()?;
+    reader.read_line(&mut version).ok()?;
+    let name = name.trim();
+    if name.is_empty() || !name.is_ascii() {
+	return None;
+    }
+    Some((name.to_string(), version.trim().parse().ok()?))
+}
+
+
+// Blocks and channels from a name part like "b18c384nbt".
+fn parse_architecture(name: &str) -> (Option<u32>, Option<u32>) {
+    for part in name.split(['-', '.']) {
+	let Some(rest) = part.strip_prefix('b') else { continue; };
+	let Some((blocks, rest)) = rest.split_once('c') else { continue; };
+	let channels: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+	if let (Ok(blocks), Ok(channels)) = (blocks.parse(), channels.parse()) {
+	    return (Some(blocks), Some(channels));
+	}
+    }
+    (None, None)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::env;
+    use std::io::Write;
+
+    fn write_network(path: &Path, header: &str) {
+	let mut encoder = GzEncoder::new(fs::File::create(path).unwrap(), Compression::default());
+	encoder.write_all(header.as_bytes()).unwrap();
+	encoder.write_all(&[0, 1, 2, 3]).unwrap();
+	encoder.finish().unwrap();
+    }
+
+    #[test]
+    fn networks() {
+	let dir = env::temp_dir().join(format!("go-game-networks-{}", std::process::id()));
+	let old_dir = dir.join("katago");
+	fs::create_dir_all(&old_dir).unwrap();
+	write_network(&old_dir.join("g170e-b20c256x2-s5303129600-d1228401921.bin.gz"), "g170e-b20c256x2-s5303129600-d1228401921\n8\n");
+	fs::write(old_dir.join("g170e-b20c256x2-s5303129600-d1228401921.bin.gz.sha256"), "abc").unwrap();
+	fs::write(old_dir.join("katago.zip"), "zip").unwrap();
+
+	let manager = NetworkManager::new(&old_dir.join("networks"));
+	assert_eq!(manager.list().unwrap(), vec![]);
+	assert_eq!(manager.migrate(&old_dir).unwrap(), vec!["g170e-b20c256x2-s5303129600-d1228401921.bin.gz"]);
+	assert!(old_dir.join("katago.zip").exists());
+	assert!(manager.get_path("g170e-b20c256x2-s5303129600-d1228401921.bin.gz.sha256").exists());
+
+	let human = dir.join("b18c384nbt-humanv0.bin.gz");
+	write_network(&human, "b18c384nbt-humanv0\n15\n");
+	let info = manager.add_file(&human).unwrap();
+	assert_eq!((info.blocks, info.channels, info.format_version), (Some(18), Some(384), Some(15)));
+	assert!(info.is_human);
+	fs::write(dir.join("broken.bin.gz"), "not gzip").unwrap();
+	assert!(manager.add_file(&dir.join("broken.bin.gz")).is_err());
+	assert!(manager.add_file(&dir.join("katago.zip")).is_err());
+
+	let networks = manager.list().unwrap();
+	assert_eq!(networks.len(), 2);
+	assert_eq!(networks[1].model_name.as_deref(), Some("g170e-b20c256x2-s5303129600-d1228401921"));
+	assert_eq!(networks[1].get_description(), format!("b20c256, {:.1} MB", networks[1].size as f64 / (1024.0 * 1024.0)));
+	manager.remove("b18c384nbt-humanv0.bin.gz").unwrap();
+	assert_eq!(manager.list().unwrap().len(), 1);
+	fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,21 @@
 use std::process::{self, Child, ChildStdin, ChildStdout, ChildStderr};
 use std::io::{Read, Chain};
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
 
-// Child that is killed when dropped. Plus, some other features.
+// Time a child gets to exit after SIGTERM before it is killed.
+const TERMINATE_TIMEOUT: Duration = Duration::from_secs(2);
+
+
+// Child that is terminated when dropped. Plus, some other features.
 pub struct SmartChild {
     child: Child,
+    own_group: bool, // Child leads its own process group, which is signaled as a whole.
 }
 
 
@@ -12,21 +23,38 @@ impl SmartChild {
     pub fn from_child(child: Child) -> Self {
 	Self {
 	    child: child,
+	    own_group: false,
 	}
     }
 
     pub fn from_command_str(command: &str) -> Result<Self, String> {
-	let (program, args) = parse_command_str(&command)?;
+	Self::spawn(&LaunchSpec::from_command_str(command)?)
+    }
 
-	let child = process::Command::new(program)
-	    .args(args)
+    // Starts the program of the spec with piped stdin, stdout and
+    // stderr. The init script is not written, stdin belongs to the
+    // caller. On Unix the program gets its own process group so that
+    // helper processes it starts are terminated with it. A side effect
+    // is that Ctrl-C in the terminal doesn't reach it.
+    pub fn spawn(spec: &LaunchSpec) -> Result<Self, String> {
+	let mut cmd = process::Command::new(&spec.program);
+	cmd.args(&spec.args)
+	    .envs(&spec.env)
 	    .stdin(process::Stdio::piped())
 	    .stdout(process::Stdio::piped())
-	    .stderr(process::Stdio::piped())
-	    .spawn()
-	    .map_err(|_| format!("Failed to start child process: {:?}", command))?;
+	    .stderr(process::Stdio::piped());
+	if let Some(dir) = &spec.working_dir {
+	    cmd.current_dir(dir);
+	}
+	#[cfg(unix)]
+	{
+	    use std::os::unix::process::CommandExt;
+	    cmd.process_group(0);
+	}
+	let child = cmd.spawn()
+	    .map_err(|e| format!("Failed to start child process {}: {e}", spec.to_command_str()))?;
 
-	Ok(Self::from_child(child))
+	Ok(Self { child: child, own_group: cfg!(unix) })
     }
 
     pub fn take_stdin(&mut self) -> Result<ChildStdin, String> {
@@ -52,6 +80,69 @@ impl SmartChild {
     pub fn try_wait(&mut self) -> Result<Option<process::ExitStatus>, String> {
 	self.child.try_wait().map_err(|_| "Error at try_wait child!".to_string())
     }
+
+    // Waits up to timeout for the child to exit.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Result<Option<process::ExitStatus>, String> {
+	let start = Instant::now();
+	loop {
+	    if let Some(status) = self.try_wait()? {
+		return Ok(Some(status));
+	    }
+	    if start.elapsed() >= timeout {
+		return Ok(None);
+	    }
+	    thread::sleep(Duration::from_millis(20));
+	}
+    }
+
+    // Gives the child up to grace_period to exit by itself, e.g. after
+    // GTP quit, then sends SIGTERM and finally SIGKILL. Processes left
+    // in the child's process group are killed as well.
+    pub fn terminate(&mut self, grace_period: Duration) -> Result<process::ExitStatus, String> {
+	let status = match self.wait_timeout(grace_period)? {
+	    Some(status) => status,
+	    None => {
+		println!("Child process has not exited, terminating!");
+		self.signal(Signal::Terminate);
+		match self.wait_timeout(TERMINATE_TIMEOUT)? {
+		    Some(status) => status,
+		    None => {
+			println!("Child process has not terminated, killing!");
+			self.signal(Signal::Kill);
+			self.wait()?
+		    },
+		}
+	    },
+	};
+	if self.own_group {
+	    self.signal(Signal::Kill); // Leftover helper processes.
+	}
+	Ok(status)
+    }
+
+    #[cfg(unix)]
+    fn signal(&mut self, signal: Signal) {
+	let signal = match signal {
+	    Signal::Terminate => libc::SIGTERM,
+	    Signal::Kill => libc::SIGKILL,
+	};
+	let pid = self.child.id() as libc::pid_t;
+	let target = if self.own_group { -pid } else { pid };
+	// Fails harmlessly with ESRCH when nothing is left. The pid of
+	// the group cannot be reused while the group has members.
+	unsafe { libc::kill(target, signal); }
+    }
+
+    #[cfg(not(unix))]
+    fn signal(&mut self, _signal: Signal) {
+	let _ = self.child.kill();
+    }
+}
+
+
+enum Signal {
+    Terminate,
+    Kill,
 }
 
 
@@ -61,11 +152,12 @@ impl Drop for SmartChild {
 	    Ok(Some(_status)) => {
 		// child exited with status
 		println!("Child process already exited. No need to kill.");
+		if self.own_group {
+		    self.signal(Signal::Kill);
+		}
 	    },
 	    Ok(None) => {
-		// child has not exited, kill it
-		println!("Child process has not exited, killing!");
-		let _ = self.child.kill();
+		let _ = self.terminate(Duration::ZERO);
 	    }
 	    Err(_) => (),
 	}
@@ -73,11 +165,163 @@ impl Drop for SmartChild {
 }
 
 
-pub fn parse_command_str(command: &str) -> Result<(&str, Vec<&str>), String> {
-    let mut args = command.split_whitespace();
-    let program = args.next().ok_or("Empty command")?;
-    let args: Vec<_> = args.collect();
-    Ok((program, args))
+// How to start a program. The argument vector is either given
+// explicitly or parsed from a command line with shell-style quoting.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LaunchSpec {
+    pub program: OsString,
+    pub args: Vec<OsString>,
+    pub working_dir: Option<PathBuf>, // None is the current directory.
+    pub env: BTreeMap<String, String>, // Added to the inherited environment.
+    pub init_script: Vec<String>, // Lines for stdin after the program starts, e.g. GTP commands.
 }
 
 
+impl LaunchSpec {
+    pub fn from_argv<S: AsRef<OsStr>>(argv: &[S]) -> Result<Self, String> {
+	let (program, args) = argv.split_first().ok_or("Empty command")?;
+	Ok(Self {
+	    program: program.as_ref().to_os_string(),
+	    args: args.iter().map(|x| x.as_ref().to_os_string()).collect(),
+	    ..Self::default()
+	})
+    }
+
+    pub fn from_command_str(command: &str) -> Result<Self, String> {
+	Self::from_argv(&parse_command_str(command)?)
+    }
+
+    // Appends the lines of a script file to the init script, skipping
+    // empty lines and '#' comments.
+    pub fn load_init_script(&mut self, path: &Path) -> Result<(), String> {
+	let content = fs::read_to_string(path).map_err(|e| format!("Cannot read init script {path:?}: {e}"))?;
+	self.init_script.extend(content.lines()
+				.map(|x| x.trim())
+				.filter(|x| !x.is_empty() && !x.starts_with('#'))
+				.map(|x| x.to_string()));
+	Ok(())
+    }
+
+    // Name of the program without directory and extension.
+    pub fn get_program_name(&self) -> String {
+	match Path::new(&self.program).file_stem() {
+	    Some(name) => name.to_string_lossy().to_string(),
+	    None => self.program.to_string_lossy().to_string(),
+	}
+    }
+
+    // Command line that parse_command_str splits back into the same
+    // argument vector, for logs and transcripts.
+    pub fn to_command_str(&self) -> String {
+	let argv: Vec<_> = std::iter::once(&self.program).chain(&self.args)
+	    .map(|x| quote_arg(&x.to_string_lossy()))
+	    .collect();
+	argv.join(" ")
+    }
+}
+
+
+// Splits a command line into arguments like a shell, without any
+// expansion. Single quotes keep everything literally. In double quotes
+// a backslash escapes '"' and '\'. Outside quotes a backslash escapes
+// whitespace, quotes and '\', and is literal before anything else, so
+// that Windows paths need no escaping.
+pub fn parse_command_str(command: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None; // None between arguments.
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+	match c {
+	    c if c.is_whitespace() => {
+		if let Some(a) = arg.take() {
+		    args.push(a);
+		}
+	    },
+	    '\'' => {
+		let a = arg.get_or_insert_with(String::new);
+		loop {
+		    match chars.next() {
+			Some('\'') => { break; },
+			Some(c) => { a.push(c); },
+			None => { return Err(format!("Unterminated single quote in command: {command}")); },
+		    }
+		}
+	    },
+	    '"' => {
+		let a = arg.get_or_insert_with(String::new);
+		loop {
+		    match chars.next() {
+			Some('"') => { break; },
+			Some('\\') if matches!(chars.peek(), Some('"' | '\\')) => { a.push(chars.next().unwrap()); },
+			Some(c) => { a.push(c); },
+			None => { return Err(format!("Unterminated double quote in command: {command}")); },
+		    }
+		}
+	    },
+	    '\\' => {
+		let a = arg.get_or_insert_with(String::new);
+		match chars.peek() {
+		    Some(&c) if c.is_whitespace() || matches!(c, '\'' | '"' | '\\') => {
+			a.push(c);
+			chars.next();
+		    },
+		    _ => { a.push('\\'); },
+		}
+	    },
+	    c => { arg.get_or_insert_with(String::new).push(c); },
+	}
+    }
+    if let Some(a) = arg {
+	args.push(a);
+    }
+
+    if args.is_empty() {
+	Err("Empty command".to_string())
+    } else {
+	Ok(args)
+    }
+}
+
+
+fn quote_arg(s: &str) -> String {
+    if !s.is_empty() && !s.chars().any(|c| c.is_whitespace() || matches!(c, '\'' | '"' | '\\')) {
+	return s.to_string();
+    }
+    let mut quoted = String::from('"');
+    for c in s.chars() {
+	if matches!(c, '"' | '\\') {
+	    quoted.push('\\');
+	}
+	quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_str() {
+	let argv = |s| parse_command_str(s).unwrap();
+	assert_eq!(argv("katago  gtp -model m.bin.gz"), vec!["katago", "gtp", "-model", "m.bin.gz"]);
+	assert_eq!(argv("'/home/me/Go Engines/katago' gtp -config \"a b.cfg\""), vec!["/home/me/Go Engines/katago", "gtp", "-config", "a b.cfg"]);
+	assert_eq!(argv("My\\ Engine \"say \\\"hi\\\"\" '' x'y'z"), vec!["My Engine", "say \"hi\"", "", "xyz"]);
+	assert_eq!(argv("C:\\Go\\gnugo.exe --mode gtp"), vec!["C:\\Go\\gnugo.exe", "--mode", "gtp"]);
+	assert!(parse_command_str("  ").is_err());
+	assert!(parse_command_str("gnugo 'level").is_err());
+
+	let mut spec = LaunchSpec::from_argv(&["/opt/Go Engines/katago", "gtp", "", "say \"hi\"", "C:\\x"]).unwrap();
+	assert_eq!(LaunchSpec::from_command_str(&spec.to_command_str()).unwrap(), spec);
+	assert_eq!(spec.get_program_name(), "katago");
+
+	let path = std::env::temp_dir().join(format!("go-game-init-script-{}", std::process::id()));
+	fs::write(&path, "# Setup\nboardsize 9\n\nkomi 6.5\n").unwrap();
+	spec.load_init_script(&path).unwrap();
+	fs::remove_file(&path).unwrap();
+	assert_eq!(spec.init_script, vec!["boardsize 9", "komi 6.5"]);
+    }
+}
@@ -29,6 +29,15 @@ where
 }
 
 
+impl SmartHandle {
+    // Asks the thread to quit without waiting for it. Dropping the
+    // handle still joins the thread.
+    pub fn send_kill_signal(&self) {
+	let _ = self.kill_signal_tx.send(());
+    }
+}
+
+
 impl Drop for SmartHandle {
     fn drop(&mut self) {
 	let _ = self.kill_signal_tx.send(());
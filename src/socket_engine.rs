@@ -0,0 +1,278 @@
+use crate::gtp::*;
+use crate::gtp_katago::KataGoGtpExtensions;
+use crate::gtp_transcript::Transcript;
+use crate::gtp_channel::{GtpChannel, PendingResponse};
+use crate::game_engine::GameEngine;
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+// Engine that runs elsewhere and serves GTP on a socket, e.g. KataGo
+// on a strong machine behind socat or netcat.
+pub struct SocketEngine {
+    address: String,
+    stream: Stream, // Kept for closing the connection.
+    channel: GtpChannel,
+    transcript: Option<Transcript>,
+    capabilities: Option<Capabilities>, // Queried on first use.
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+
+impl SocketEngine {
+    // Address is either "host:port" for TCP or "unix:<path>" for a
+    // Unix domain socket.
+    pub fn connect(address: &str) -> Result<Self, GtpError> {
+	let stream = if let Some(path) = address.strip_prefix("unix:") {
+	    connect_unix(path)?
+	} else {
+	    let socket_addr = address.to_socket_addrs()
+		.map_err(|e| GtpError::Io(format!("Cannot resolve {address}: {e}")))?
+		.next().ok_or(GtpError::Io(format!("{address} doesn't resolve to any address!")))?;
+	    let stream = TcpStream::connect_timeout(&socket_addr, CONNECT_TIMEOUT)
+		.map_err(|e| GtpError::Io(format!("Cannot connect to {address}: {e}")))?;
+	    let _ = stream.set_nodelay(true);
+	    Stream::Tcp(stream)
+	};
+
+	// Reading with the channel, like ChildProcessEngine, so that a
+	// hanging engine can be timed out.
+	let channel = match &stream {
+	    Stream::Tcp(s) => {
+		let reader = s.try_clone().map_err(|e| GtpError::Io(e.to_string()))?;
+		let writer = s.try_clone().map_err(|e| GtpError::Io(e.to_string()))?;
+		GtpChannel::new(reader, writer)
+	    },
+	    #[cfg(unix)]
+	    Stream::Unix(s) => {
+		let reader = s.try_clone().map_err(|e| GtpError::Io(e.to_string()))?;
+		let writer = s.try_clone().map_err(|e| GtpError::Io(e.to_string()))?;
+		GtpChannel::new(reader, writer)
+	    },
+	};
+
+	Ok(Self {
+	    address: address.to_string(),
+	    stream: stream,
+	    channel: channel,
+	    transcript: None,
+	    capabilities: None,
+	})
+    }
+
+    fn shutdown(&self) {
+	let _ = match &self.stream {
+	    Stream::Tcp(s) => s.shutdown(Shutdown::Both),
+	    #[cfg(unix)]
+	    Stream::Unix(s) => s.shutdown(Shutdown::Both),
+	};
+    }
+}
+
+
+#[cfg(unix)]
+fn connect_unix(path: &str) -> Result<Stream, GtpError> {
+    let stream = UnixStream::connect(path)
+	.map_err(|e| GtpError::Io(format!("Cannot connect to {path}: {e}")))?;
+    Ok(Stream::Unix(stream))
+}
+
+
+#[cfg(not(unix))]
+fn connect_unix(path: &str) -> Result<Stream, GtpError> {
+    Err(GtpError::Io(format!("Unix sockets are not supported on this platform: {path}")))
+}
+
+
+impl GTPEngineRaw for SocketEngine {
+    fn gen_command_id(&mut self) -> u32 {
+	self.channel.gen_command_id()
+    }
+
+    fn write_to_engine(&self, s: &str) -> Result<(), GtpError> {
+	self.channel.write_to_engine(s)
+    }
+
+    fn read_from_engine(&mut self) -> Result<String, GtpError> {
+	match self.channel.read_from_engine() {
+	    Err(GtpError::ProcessExited(_)) => Err(GtpError::ProcessExited(format!("Connection to {} was closed.", self.address))),
+	    r => r,
+	}
+    }
+
+    fn send_streaming_command(&mut self, command: &str) -> Result<PendingResponse, GtpError> {
+	self.channel.send(command)
+    }
+
+    fn get_transcript(&mut self) -> Option<&mut Transcript> {
+	self.transcript.as_mut()
+    }
+}
+
+
+impl GTPEngineMinimal for SocketEngine {
+    // Sends quit and closes the connection, which ends the reader
+    // thread of the channel. Dropping the engine closes the
+    // connection without quit.
+    fn quit(mut self) -> Result<(), GtpError> {
+	self.send_command("quit")?;
+	self.shutdown();
+	Ok(())
+    }
+}
+
+
+impl GTPEngineTournament for SocketEngine {}
+
+
+impl GTPEngineRegression for SocketEngine {}
+
+
+impl GTPEngineExtendedCorePlay for SocketEngine {}
+
+
+impl GTPEngineExtendedTournament for SocketEngine {}
+
+
+impl GTPEngineDebug for SocketEngine {}
+
+
+impl GTPEngineLeelaZero for SocketEngine {}
+
+
+impl KataGoGtpExtensions for SocketEngine {}
+
+
+impl GameEngine for SocketEngine {
+    fn get_capabilities(&self) -> Option<&Capabilities> {
+	self.capabilities.as_ref()
+    }
+
+    fn query_capabilities(&mut self) -> Result<&Capabilities, GtpError> {
+	if self.capabilities.is_none() {
+	    self.capabilities = Some(Capabilities::query(self)?);
+	}
+	Ok(self.capabilities.as_ref().unwrap())
+    }
+
+    fn record_transcript(&mut self, dir: &Path) -> Result<PathBuf, String> {
+	let transcript = Transcript::create(dir, "remote", &self.address)?;
+	let path = transcript.get_path().to_path_buf();
+	println!("Recording GTP transcript to {path:?}");
+	self.transcript = Some(transcript);
+	Ok(path)
+    }
+
+    fn set_command_timeout(&mut self, timeout: Option<Duration>) {
+	self.channel.set_command_timeout(timeout);
+    }
+
+    fn get_channel(&self) -> Option<GtpChannel> {
+	Some(self.channel.clone())
+    }
+
+    // Connects again to the same address. The engine behind it may
+    // have been restarted by whoever runs it.
+    fn restart(&mut self) -> Result<(), GtpError> {
+	println!("Reconnecting to engine: {}", self.address);
+	let mut engine = Self::connect(&self.address)?;
+	engine.set_command_timeout(self.channel.get_command_timeout());
+	engine.transcript = self.transcript.take();
+	*self = engine; // Old connection is closed.
+	Ok(())
+    }
+}
+
+
+impl Drop for SocketEngine {
+    fn drop(&mut self) {
+	// Unblocks the reader thread.
+	self.shutdown();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // Answers list_commands, name and genmove, and stops after quit.
+    fn serve<S: Read + Write>(stream: S) {
+	let mut reader = BufReader::new(stream);
+	let mut line = String::new();
+	while reader.read_line(&mut line).unwrap() > 0 {
+	    let tokens: Vec<_> = line.split_whitespace().collect();
+	    if let [id, command, ..] = tokens[..] {
+		let response = match command {
+		    "list_commands" => format!("={id} list_commands\nname\ngenmove\nquit\n\n"),
+		    "name" => format!("={id} Remote\n\n"),
+		    "genmove" => format!("={id} C3\n\n"),
+		    "quit" => format!("={id}\n\n"),
+		    _ => format!("?{id} unknown command\n\n"),
+		};
+		reader.get_mut().write_all(response.as_bytes()).unwrap();
+		if command == "quit" {
+		    break;
+		}
+	    }
+	    line.clear();
+	}
+    }
+
+    #[test]
+    fn tcp() {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let address = listener.local_addr().unwrap().to_string();
+	let server = thread::spawn(move || serve(listener.accept().unwrap().0));
+
+	let mut engine = SocketEngine::connect(&address).unwrap();
+	assert!(!engine.query_capabilities().unwrap().has(Capability::Undo));
+	assert_eq!(engine.name().unwrap(), "Remote");
+	assert_eq!(engine.genmove(Color::Black).unwrap(), GenMove::Play(Vertex::Coordinate(2, 2)));
+	assert!(matches!(engine.undo(), Err(GtpError::EngineFailure(_))));
+	engine.quit().unwrap();
+	server.join().unwrap();
+    }
+
+    #[test]
+    fn closed_connection() {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let address = listener.local_addr().unwrap().to_string();
+	let server = thread::spawn(move || drop(listener.accept().unwrap()));
+
+	let mut engine = SocketEngine::connect(&address).unwrap();
+	server.join().unwrap();
+	assert!(matches!(engine.name(), Err(GtpError::ProcessExited(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix() {
+	use std::os::unix::net::UnixListener;
+
+	let path = std::env::temp_dir().join(format!("go-game-socket-engine-{}", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	let listener = UnixListener::bind(&path).unwrap();
+	let server = thread::spawn(move || serve(listener.accept().unwrap().0));
+
+	let mut engine = SocketEngine::connect(&format!("unix:{}", path.to_str().unwrap())).unwrap();
+	assert_eq!(engine.name().unwrap(), "Remote");
+	engine.quit().unwrap();
+	server.join().unwrap();
+	std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,24 @@
+// Checks the fake engine binary on its own. Having an integration
+// test also makes cargo build the binary, which the unit tests of
+// ChildProcessEngine launch.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+
+#[test]
+fn fake_gtp_engine() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_fake_gtp_engine"))
+	.stdin(Stdio::piped())
+	.stdout(Stdio::piped())
+	.spawn()
+	.unwrap();
+
+    child.stdin.take().unwrap()
+	.write_all(b"1 name\n\n2 boardsize 1\n\nknown_command play\n\n3 quit\n\n")
+	.unwrap();
+    let output = child.wait_with_output().unwrap();
+    let output = String::from_utf8(output.stdout).unwrap();
+
+    assert_eq!(output, "=1 Fake\n\n?2 unacceptable size\n\n= true\n\n=3 \n\n");
+}